@@ -1,32 +1,324 @@
-from typing import List, Optional, Tuple, final
+from os import PathLike
+from typing import (
+    Any,
+    Callable,
+    Dict,
+    Iterable,
+    List,
+    Literal,
+    Optional,
+    Tuple,
+    Union,
+    final,
+    overload,
+)
+
+Metric = Literal["levenshtein", "osa", "damerau_levenshtein"]
+BKTreeMetric = Union[
+    Literal["levenshtein", "osa", "damerau_levenshtein", "hamming"],
+    Callable[[str, str], int],
+]
+Segmentation = Literal["codepoints", "graphemes"]
+Normalization = Literal[
+    "none", "lowercase", "casefold", "nfc", "nfkc", "strip_accents"
+]
+Normalizer = Optional[Union[Normalization, List[Normalization]]]
+Selection = Literal["all", "longest", "non_overlapping"]
+Editop = Tuple[Literal["replace", "insert", "delete"], int, int]
+Opcode = Tuple[Literal["equal", "replace", "insert", "delete"], int, int, int, int]
 
 @final
 class Trie:
-    def __init__(self, items: Optional[List[str]] = ...) -> None: ...
+    def __init__(
+        self,
+        items: Optional[List[str]] = ...,
+        segmentation: Segmentation = ...,
+        normalizer: Normalizer = ...,
+    ) -> None: ...
     @staticmethod
     def new() -> "Trie": ...
     def insert(self, value: str) -> None: ...
+    def remove(self, value: str) -> bool: ...
     def get(self, value: str) -> Optional[str]: ...
     def contains(self, value: str) -> bool: ...
     def values(self) -> List[str]: ...
+    def to_bytes(self) -> bytes: ...
+    @staticmethod
+    def from_bytes(
+        data: bytes,
+        segmentation: Optional[Segmentation] = ...,
+        normalizer: Normalizer = ...,
+    ) -> "Trie": ...
+    def save(self, path: Union[str, PathLike[str]]) -> None: ...
+    @staticmethod
+    def load(
+        path: Union[str, PathLike[str]],
+        segmentation: Optional[Segmentation] = ...,
+        normalizer: Normalizer = ...,
+    ) -> "Trie": ...
     def find_one(
-        self, query: str, max_edits: Optional[int] = ...
+        self,
+        query: str,
+        max_edits: Optional[int] = ...,
+        metric: Metric = ...,
+        min_similarity: Optional[float] = ...,
+        weights: Tuple[int, int, int] = ...,
+        substitutions: Optional[Substitutions] = ...,
     ) -> Optional[Tuple[str, int]]: ...
+    def find_one_with_ops(
+        self, query: str, max_edits: Optional[int] = ...
+    ) -> Optional[Tuple[str, int, List[Editop]]]: ...
+    def find_one_batch(
+        self,
+        queries: List[str],
+        max_edits: Optional[int] = ...,
+        metric: Metric = ...,
+        weights: Tuple[int, int, int] = ...,
+        substitutions: Optional[Substitutions] = ...,
+    ) -> List[Optional[Tuple[str, int]]]: ...
+    def find_all(
+        self,
+        query: str,
+        max_edits: int,
+        metric: Metric = ...,
+        weights: Tuple[int, int, int] = ...,
+        substitutions: Optional[Substitutions] = ...,
+    ) -> List[Tuple[str, int]]: ...
+    def find_k(
+        self,
+        query: str,
+        k: int,
+        max_edits: Optional[int] = ...,
+        metric: Metric = ...,
+        weights: Tuple[int, int, int] = ...,
+        substitutions: Optional[Substitutions] = ...,
+    ) -> List[Tuple[str, int]]: ...
+    def starts_with(self, prefix: str) -> List[str]: ...
+    def fuzzy_prefix(
+        self,
+        query: str,
+        max_edits: int,
+        limit: Optional[int] = ...,
+        metric: Metric = ...,
+        weights: Tuple[int, int, int] = ...,
+        substitutions: Optional[Substitutions] = ...,
+    ) -> List[Tuple[str, int]]: ...
+    def scan(
+        self, text: str, max_edits: int, selection: Selection = ...
+    ) -> List[Tuple[int, int, str, int]]: ...
 
 @final
 class BKTree:
-    def __init__(self, items: Optional[List[str]] = ...) -> None: ...
+    def __init__(
+        self,
+        items: Optional[List[str]] = ...,
+        metric: BKTreeMetric = ...,
+        segmentation: Segmentation = ...,
+    ) -> None: ...
     @staticmethod
     def new() -> "BKTree": ...
     def insert(self, value: str) -> None: ...
+    def remove(self, value: str) -> bool: ...
     def get(self, value: str) -> Optional[str]: ...
     def contains(self, value: str) -> bool: ...
     def values(self) -> List[str]: ...
+    def to_bytes(self) -> bytes: ...
+    @staticmethod
+    def from_bytes(
+        data: bytes,
+        metric: Optional[BKTreeMetric] = ...,
+        segmentation: Optional[Segmentation] = ...,
+    ) -> "BKTree": ...
+    def save(self, path: Union[str, PathLike[str]]) -> None: ...
+    @staticmethod
+    def load(
+        path: Union[str, PathLike[str]],
+        metric: Optional[BKTreeMetric] = ...,
+        segmentation: Optional[Segmentation] = ...,
+    ) -> "BKTree": ...
     def find_one(
-        self, query: str, max_edits: Optional[int] = ...
+        self,
+        query: str,
+        max_edits: Optional[int] = ...,
+        min_similarity: Optional[float] = ...,
     ) -> Optional[Tuple[str, int]]: ...
+    def find_one_batch(
+        self, queries: List[str], max_edits: Optional[int] = ...
+    ) -> List[Optional[Tuple[str, int]]]: ...
+    def find_all(self, query: str, max_edits: int) -> List[Tuple[str, int]]: ...
+    def find_k(
+        self, query: str, k: int, max_edits: Optional[int] = ...
+    ) -> List[Tuple[str, int]]: ...
+
+@final
+class FrozenTrie:
+    def __init__(self, items: List[str]) -> None: ...
+    @staticmethod
+    def from_sorted(items: List[str]) -> "FrozenTrie": ...
+    @staticmethod
+    def from_trie(trie: Trie) -> "FrozenTrie": ...
+    def to_bytes(self) -> bytes: ...
+    @staticmethod
+    def from_bytes(data: bytes) -> "FrozenTrie": ...
+    def save(self, path: Union[str, PathLike[str]]) -> None: ...
+    @staticmethod
+    def open(path: Union[str, PathLike[str]]) -> "FrozenTrie": ...
+    def __len__(self) -> int: ...
+    def get(self, value: str) -> Optional[str]: ...
+    def contains(self, value: str) -> bool: ...
+    def values(self) -> List[str]: ...
+    def find_one(
+        self, query: str, max_edits: Optional[int] = ..., metric: Metric = ...
+    ) -> Optional[Tuple[str, int]]: ...
+    def find_all(
+        self, query: str, max_edits: int, metric: Metric = ...
+    ) -> List[Tuple[str, int]]: ...
+
+@final
+class TrieMap:
+    def __init__(
+        self,
+        items: Optional[Union[Dict[str, Any], Iterable[Tuple[str, Any]]]] = ...,
+        segmentation: Segmentation = ...,
+        normalizer: Normalizer = ...,
+    ) -> None: ...
+    @staticmethod
+    def new() -> "TrieMap": ...
+    def insert(self, key: str, value: Any) -> Optional[Any]: ...
+    def remove(self, key: str) -> bool: ...
+    def get(self, key: str) -> Optional[Tuple[str, int, Any]]: ...
+    def contains(self, key: str) -> bool: ...
+    def keys(self) -> List[str]: ...
+    def values(self) -> List[Any]: ...
+    def items(self) -> List[Tuple[str, Any]]: ...
+    def save(self, path: Union[str, PathLike[str]]) -> None: ...
+    @staticmethod
+    def load(path: Union[str, PathLike[str]]) -> "TrieMap": ...
+    def find_one(
+        self,
+        query: str,
+        max_edits: Optional[int] = ...,
+        metric: Metric = ...,
+        min_similarity: Optional[float] = ...,
+        weights: Tuple[int, int, int] = ...,
+        substitutions: Optional[Substitutions] = ...,
+    ) -> Optional[Tuple[str, int, Any]]: ...
+    def find_all(
+        self,
+        query: str,
+        max_edits: int,
+        metric: Metric = ...,
+        weights: Tuple[int, int, int] = ...,
+        substitutions: Optional[Substitutions] = ...,
+    ) -> List[Tuple[str, int, Any]]: ...
+    def find_k(
+        self,
+        query: str,
+        k: int,
+        max_edits: Optional[int] = ...,
+        metric: Metric = ...,
+        weights: Tuple[int, int, int] = ...,
+        substitutions: Optional[Substitutions] = ...,
+    ) -> List[Tuple[str, int, Any]]: ...
+    def starts_with(self, prefix: str) -> List[Tuple[str, Any]]: ...
+    def fuzzy_prefix(
+        self,
+        query: str,
+        max_edits: int,
+        limit: Optional[int] = ...,
+        metric: Metric = ...,
+        weights: Tuple[int, int, int] = ...,
+        substitutions: Optional[Substitutions] = ...,
+    ) -> List[Tuple[str, int, Any]]: ...
+    def scan(
+        self, text: str, max_edits: int, selection: Selection = ...
+    ) -> List[Tuple[int, int, str, int, Any]]: ...
+
+@final
+class BKTreeMap:
+    def __init__(
+        self,
+        items: Optional[Union[Dict[str, Any], Iterable[Tuple[str, Any]]]] = ...,
+        metric: BKTreeMetric = ...,
+        segmentation: Segmentation = ...,
+    ) -> None: ...
+    @staticmethod
+    def new() -> "BKTreeMap": ...
+    def insert(self, key: str, value: Any) -> Optional[Any]: ...
+    def remove(self, key: str) -> bool: ...
+    def get(self, key: str) -> Optional[Tuple[str, int, Any]]: ...
+    def contains(self, key: str) -> bool: ...
+    def keys(self) -> List[str]: ...
+    def values(self) -> List[Any]: ...
+    def items(self) -> List[Tuple[str, Any]]: ...
+    def save(self, path: Union[str, PathLike[str]]) -> None: ...
+    @staticmethod
+    def load(path: Union[str, PathLike[str]]) -> "BKTreeMap": ...
+    def find_one(
+        self,
+        query: str,
+        max_edits: Optional[int] = ...,
+        min_similarity: Optional[float] = ...,
+    ) -> Optional[Tuple[str, int, Any]]: ...
+    def find_all(self, query: str, max_edits: int) -> List[Tuple[str, int, Any]]: ...
+    def find_k(
+        self, query: str, k: int, max_edits: Optional[int] = ...
+    ) -> List[Tuple[str, int, Any]]: ...
+
+@final
+class Substitutions:
+    def __init__(
+        self, default: int = ..., costs: Optional[Dict[Tuple[str, str], int]] = ...
+    ) -> None: ...
+    @staticmethod
+    def qwerty(adjacent: int = ..., default: int = ...) -> "Substitutions": ...
+    @staticmethod
+    def ocr(confusable: int = ..., default: int = ...) -> "Substitutions": ...
+    def __getitem__(self, pair: Tuple[str, str]) -> int: ...
+    def __setitem__(self, pair: Tuple[str, str], cost: int) -> None: ...
 
-def levenshtein(a: str, b: str) -> int: ...
+@overload
+def levenshtein(
+    a: str,
+    b: str,
+    weights: Tuple[int, int, int] = ...,
+    substitutions: Optional[Substitutions] = ...,
+    score_cutoff: None = ...,
+    segmentation: Segmentation = ...,
+) -> int: ...
+@overload
+def levenshtein(
+    a: str,
+    b: str,
+    weights: Tuple[int, int, int] = ...,
+    substitutions: Optional[Substitutions] = ...,
+    *,
+    score_cutoff: int,
+    segmentation: Segmentation = ...,
+) -> Optional[int]: ...
+def levenshtein_similarity(a: str, b: str) -> float: ...
 def levenshtein_extract(
-    query: str, choices: List[str]
+    query: str, choices: List[str], min_similarity: Optional[float] = ...
 ) -> Optional[Tuple[str, int, int]]: ...
+def levenshtein_extract_k(
+    query: str, choices: Iterable[str], k: int
+) -> List[Tuple[str, int, int]]: ...
+def levenshtein_extract_all(
+    query: str, choices: Iterable[str], max_edits: int
+) -> List[Tuple[str, int, int]]: ...
+def levenshtein_extract_batch(
+    queries: List[str], choices: List[str]
+) -> List[Optional[Tuple[str, int, int]]]: ...
+def cdist(
+    queries: List[str], choices: List[str], max_edits: Optional[int] = ...
+) -> Any: ...
+def fuzzy_find(
+    pattern: str, text: str, max_edits: int
+) -> List[Tuple[int, int, int]]: ...
+def partial_ratio(a: str, b: str) -> float: ...
+def editops(a: str, b: str) -> List[Editop]: ...
+def opcodes(a: str, b: str) -> List[Opcode]: ...
+def osa(a: str, b: str) -> int: ...
+def damerau_levenshtein(a: str, b: str) -> int: ...
+def osa_similarity(a: str, b: str) -> float: ...
+def hamming(a: str, b: str) -> int: ...
@@ -1,50 +1,194 @@
-use pyo3::prelude::*;
+//! BK-tree for searching with any metric
+
 use std::collections::hash_map::Entry::{Occupied, Vacant};
 use std::collections::HashMap;
-use std::iter::once;
 
-use crate::levenshtein;
-use crate::levenshtein::LevenshteinAutomaton;
+use crate::heap::{BoundedHeap, Keyed};
+use crate::metric::{BuiltinMetric, Levenshtein, Metric};
+use crate::segmentation::Segmentation;
+use crate::serialize::{self, DecodeError, Reader, Writer};
+use crate::similarity::MinSimilarity;
 
-#[derive(Debug, Default, Clone)]
-struct Tree {
-    value: String,
+#[derive(Debug, Clone)]
+struct Tree<V> {
+    key: String,
+    // Removed keys are kept for the structure until the subtree is rebuilt
+    value: Option<V>,
+    // Number of values and nodes in the subtree, including this one
+    len: usize,
+    size: usize,
     // Expensive to iterate over HashMap as O(capacity) rather than O(len)
     children_index: HashMap<u32, usize>,
-    children: Vec<(u32, Tree)>,
+    children: Vec<(u32, Tree<V>)>,
+}
+
+impl<V> Default for Tree<V> {
+    fn default() -> Self {
+        Self {
+            key: String::new(),
+            value: None,
+            len: 0,
+            size: 0,
+            children_index: HashMap::new(),
+            children: Vec::new(),
+        }
+    }
 }
 
-impl Tree {
-    fn new(value: String) -> Self {
+enum Inserted<V> {
+    Replaced(V),
+    Revived,
+    New,
+}
+
+impl<V> Tree<V> {
+    fn new(key: String, value: V) -> Self {
         Self {
-            value,
+            key,
+            value: Some(value),
+            len: 1,
+            size: 1,
             ..Default::default()
         }
     }
 
-    fn insert(&mut self, value: String) {
-        let distance = levenshtein::levenshtein(&value, &self.value);
-        if distance == 0 {
-            return;
+    /// Distances from key to the nodes on its path, ending at a node with the
+    /// same key or one without a child at the last distance
+    fn path(&self, key: &str, metric: &impl Metric) -> Vec<u32> {
+        let mut path = Vec::new();
+        let mut node = self;
+        loop {
+            let distance = metric.distance(key, &node.key);
+            path.push(distance);
+            match node.children_index.get(&distance) {
+                Some(&idx) if distance != 0 => node = &node.children[idx].1,
+                _ => return path,
+            }
         }
-        match self.children_index.entry(distance) {
-            Occupied(entry) => self.children[*entry.get()].1.insert(value),
-            Vacant(entry) => {
-                entry.insert(self.children.len());
-                self.children.push((distance, Self::new(value)));
+    }
+
+    fn insert(&mut self, key: String, value: V, metric: &impl Metric) -> Inserted<V> {
+        let path = self.path(&key, metric);
+        self.insert_path(&path, key, value)
+    }
+
+    fn insert_path(&mut self, path: &[u32], key: String, value: V) -> Inserted<V> {
+        let distance = path[0];
+        let inserted = if distance == 0 {
+            match self.value.replace(value) {
+                Some(previous) => return Inserted::Replaced(previous),
+                None => Inserted::Revived,
+            }
+        } else {
+            match self.children_index.entry(distance) {
+                Occupied(entry) => {
+                    let child = &mut self.children[*entry.get()].1;
+                    match child.insert_path(&path[1..], key, value) {
+                        Inserted::Replaced(previous) => return Inserted::Replaced(previous),
+                        inserted => inserted,
+                    }
+                }
+                Vacant(entry) => {
+                    entry.insert(self.children.len());
+                    self.children.push((distance, Self::new(key, value)));
+                    Inserted::New
+                }
             }
         };
+        self.len += 1;
+        self.size += matches!(inserted, Inserted::New) as usize;
+        inserted
     }
 
-    fn find_one(&self, query: &str, max_edits: u32) -> Option<(&str, u32)> {
+    /// Remove the key at the end of path
+    ///
+    /// Returns None if not present, otherwise the value and number of nodes dropped
+    fn remove(&mut self, path: &[u32], metric: &impl Metric) -> Option<(V, usize)> {
+        let (value, dropped) = match path {
+            [] => return None,
+            [0] => (self.value.take()?, 0),
+            [distance, path @ ..] => {
+                let idx = *self.children_index.get(distance)?;
+                let child = &mut self.children[idx].1;
+                let (value, mut dropped) = child.remove(path, metric)?;
+                // Rebuild once most of the subtree has been removed
+                if 2 * child.len < child.size {
+                    let size = child.size;
+                    match std::mem::take(child).rebuild(metric) {
+                        Ok(Some(subtree)) => {
+                            dropped += size - subtree.size;
+                            *child = subtree;
+                        }
+                        Ok(None) => {
+                            dropped += size;
+                            self.remove_child(idx);
+                        }
+                        Err(subtree) => *child = subtree,
+                    }
+                }
+                (value, dropped)
+            }
+        };
+        self.len -= 1;
+        self.size -= dropped;
+        Some((value, dropped))
+    }
+
+    fn remove_child(&mut self, idx: usize) {
+        let (distance, _) = self.children.remove(idx);
+        self.children_index.remove(&distance);
+        for i in self.children_index.values_mut() {
+            if *i > idx {
+                *i -= 1;
+            }
+        }
+    }
+
+    /// New subtree from the values that have not been removed, or the subtree
+    /// as it was if the metric fails
+    fn rebuild(self, metric: &impl Metric) -> Result<Option<Self>, Self> {
+        // Lay out the keys before moving the values out of the subtree
+        let mut keys = self.iter().map(|x| x.0.to_string()).enumerate();
+        let Some((_, key)) = keys.next() else {
+            return Ok(None);
+        };
+        let mut layout = Tree::new(key, 0);
+        for (i, key) in keys {
+            layout.insert(key, i, metric);
+        }
+        if metric.failed() {
+            return Err(self);
+        }
+        let mut items = Vec::with_capacity(self.len);
+        self.into_items(&mut items);
+        let mut values: Vec<_> = items.into_iter().map(|x| Some(x.1)).collect();
+        Ok(Some(layout.fill(&mut values)))
+    }
+
+    fn into_items(self, items: &mut Vec<(String, V)>) {
+        if let Some(value) = self.value {
+            items.push((self.key, value));
+        }
+        for (_, child) in self.children {
+            child.into_items(items);
+        }
+    }
+
+    /// Best match within max_edits, also accepted by accept given key and distance
+    fn find_one(
+        &self,
+        query: &dyn Fn(&str) -> u32,
+        max_edits: u32,
+        accept: &dyn Fn(&str, u32) -> bool,
+    ) -> Option<(&str, u32, &V)> {
         let mut best = None;
         let mut max_edits = max_edits;
         let mut stack = vec![self];
-        let automaton = LevenshteinAutomaton::new(query);
         while let Some(node) = stack.pop() {
-            let distance = automaton.distance(&node.value);
-            if distance <= max_edits {
-                best = Some((node.value.as_str(), distance));
+            let distance = query(&node.key);
+            let accepted = distance <= max_edits && accept(&node.key, distance);
+            if let Some(value) = node.value.as_ref().filter(|_| accepted) {
+                best = Some((node.key.as_str(), distance, value));
                 if distance == 0 {
                     return best;
                 }
@@ -58,96 +202,476 @@ impl Tree {
         }
         best
     }
+
+    fn find_all<'a>(
+        &'a self,
+        query: &dyn Fn(&str) -> u32,
+        max_edits: u32,
+        result: &mut Vec<(&'a str, u32, &'a V)>,
+    ) {
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            let distance = query(&node.key);
+            if let Some(value) = node.value.as_ref().filter(|_| distance <= max_edits) {
+                result.push((node.key.as_str(), distance, value));
+            }
+            for (d, subtree) in node.children.iter() {
+                if d.abs_diff(distance) <= max_edits {
+                    stack.push(subtree);
+                }
+            }
+        }
+    }
+
+    fn find_k<'a>(
+        &'a self,
+        query: &dyn Fn(&str) -> u32,
+        heap: &mut BoundedHeap<Keyed<(u32, &'a str), &'a V>>,
+        max_edits: u32,
+    ) {
+        let mut stack = vec![self];
+        while let Some(node) = stack.pop() {
+            let distance = query(&node.key);
+            if let Some(value) = node.value.as_ref().filter(|_| distance <= max_edits) {
+                heap.push(Keyed((distance, node.key.as_str()), value));
+            }
+            // Ties with the current worst may still win on key
+            let max_edits = heap.worst().map_or(max_edits, |x| x.0 .0);
+            for (d, subtree) in node.children.iter() {
+                if d.abs_diff(distance) <= max_edits {
+                    stack.push(subtree);
+                }
+            }
+        }
+    }
+
+    fn iter<'a>(&'a self) -> Box<dyn Iterator<Item = (&'a str, &'a V)> + 'a> {
+        let item = self.value.as_ref().map(|value| (self.key.as_str(), value));
+        Box::new(
+            item.into_iter()
+                .chain(self.children.iter().flat_map(|x| x.1.iter())),
+        )
+    }
 }
 
-/// BK-tree storing the strings to search against
-#[pyclass]
-#[derive(Debug, Default, Clone)]
-pub struct BKTree {
-    tree: Option<Tree>,
+impl Tree<usize> {
+    /// Subtree with the same layout, taking each value by index
+    fn fill<V>(self, values: &mut [Option<V>]) -> Tree<V> {
+        Tree {
+            key: self.key,
+            value: self.value.and_then(|i| values[i].take()),
+            len: self.len,
+            size: self.size,
+            children_index: self.children_index,
+            children: (self.children.into_iter())
+                .map(|(distance, child)| (distance, child.fill(values)))
+                .collect(),
+        }
+    }
 }
 
-#[pymethods]
-impl BKTree {
-    #[new]
-    pub fn py_new(items: Option<Vec<String>>) -> Self {
-        items.map_or_else(Self::new, Self::from_iter)
+impl Tree<()> {
+    fn write(&self, writer: &mut Writer) {
+        writer.str(&self.key);
+        writer.u8(self.value.is_some() as u8);
+        writer.varint(self.children.len() as u64);
+        for (distance, child) in self.children.iter() {
+            writer.varint(*distance as u64);
+            child.write(writer);
+        }
+    }
+
+    fn read(reader: &mut Reader) -> Result<Self, DecodeError> {
+        let mut tree = Self {
+            key: reader.str()?.to_string(),
+            value: match reader.u8()? {
+                0 => None,
+                1 => Some(()),
+                _ => return Err(DecodeError::Invalid),
+            },
+            ..Default::default()
+        };
+        tree.len = tree.value.is_some() as usize;
+        tree.size = 1;
+        for _ in 0..reader.varint()? {
+            let distance = reader.varint()?;
+            let distance = u32::try_from(distance).map_err(|_| DecodeError::Invalid)?;
+            let child = Self::read(reader)?;
+            if distance == 0 || tree.children_index.contains_key(&distance) {
+                return Err(DecodeError::Invalid);
+            }
+            tree.len += child.len;
+            tree.size += child.size;
+            tree.children_index.insert(distance, tree.children.len());
+            tree.children.push((distance, child));
+        }
+        Ok(tree)
+    }
+}
+
+/// BK-tree storing strings with an associated value to search against
+///
+/// Searches use the triangle inequality of the metric to limit the distance
+/// calculations needed.
+#[derive(Debug, Clone)]
+pub struct BKTreeMap<V, M = Levenshtein> {
+    tree: Option<Tree<V>>,
+    metric: M,
+}
+
+impl<V, M: Default> Default for BKTreeMap<V, M> {
+    fn default() -> Self {
+        Self {
+            tree: None,
+            metric: M::default(),
+        }
     }
+}
 
-    #[staticmethod]
+impl<V> BKTreeMap<V> {
+    /// Create an empty BK-tree using the Levenshtein distance
     pub fn new() -> Self {
         Self::default()
     }
+}
 
-    pub fn insert(&mut self, value: String) {
+impl<V, M: Metric> BKTreeMap<V, M> {
+    /// Create an empty BK-tree using metric
+    pub fn with_metric(metric: M) -> Self {
+        Self { tree: None, metric }
+    }
+
+    /// Metric used by the BK-tree
+    pub fn metric(&self) -> &M {
+        &self.metric
+    }
+
+    /// Insert key with value into the BK-tree, returning any previous value
+    ///
+    /// The BK-tree is left unchanged if the metric fails, see
+    /// [`Metric::failed`].
+    pub fn insert(&mut self, key: String, value: V) -> Option<V> {
         match self.tree.as_mut() {
-            Some(t) => t.insert(value),
+            Some(t) => {
+                let path = t.path(&key, &self.metric);
+                if self.metric.failed() {
+                    return None;
+                }
+                match t.insert_path(&path, key, value) {
+                    Inserted::Replaced(previous) => Some(previous),
+                    _ => None,
+                }
+            }
             None => {
-                self.tree = Some(Tree::new(value));
+                self.tree = Some(Tree::new(key, value));
+                None
             }
         }
     }
 
-    pub fn get(&self, value: &str) -> Option<&str> {
+    /// Remove key from the BK-tree, returning its value if it was present
+    ///
+    /// Removed keys are marked as deleted, and subtrees are rebuilt once
+    /// more than half of their keys have been removed. The BK-tree is left
+    /// unchanged if the metric fails while finding key, and subtrees are not
+    /// rebuilt if it fails while rebuilding.
+    pub fn remove(&mut self, key: &str) -> Option<V> {
+        let tree = self.tree.as_mut()?;
+        let path = tree.path(key, &self.metric);
+        if self.metric.failed() {
+            return None;
+        }
+        let (value, _) = tree.remove(&path, &self.metric)?;
+        if 2 * tree.len < tree.size {
+            match std::mem::take(tree).rebuild(&self.metric) {
+                Ok(rebuilt) => self.tree = rebuilt,
+                Err(tree) => self.tree = Some(tree),
+            }
+        }
+        Some(value)
+    }
+
+    /// Get the stored key at zero distance from key and its value
+    pub fn get(&self, key: &str) -> Option<(&str, &V)> {
         let mut node = self.tree.as_ref()?;
         loop {
-            let distance = levenshtein::levenshtein(value, &node.value);
+            let distance = self.metric.distance(key, &node.key);
             if distance == 0 {
                 break;
             }
             let idx = node.children_index.get(&distance)?;
             node = &node.children[*idx].1;
         }
-        Some(&node.value)
+        node.value.as_ref().map(|value| (node.key.as_str(), value))
     }
 
-    pub fn contains(&self, value: &str) -> bool {
-        self.get(value).is_some()
+    /// Whether the BK-tree contains key
+    pub fn contains(&self, key: &str) -> bool {
+        self.get(key).is_some()
     }
 
-    pub fn values(&self) -> Vec<&str> {
-        self.iter().collect()
+    /// Find best match in BK-tree for query
+    pub fn find_one(&self, query: &str, max_edits: Option<u32>) -> Option<(&str, u32, &V)> {
+        let tree = self.tree.as_ref()?;
+        let max_edits = max_edits.unwrap_or(u32::MAX);
+        tree.find_one(&self.metric.query(query), max_edits, &|_, _| true)
     }
 
-    /// Find best match in BK-tree for query
-    pub fn find_one(&self, query: &str, max_edits: Option<u32>) -> Option<(&str, u32)> {
+    /// Find best match in BK-tree with at least min_similarity to query
+    ///
+    /// Similarity is normalized by the length of the longer string, see
+    /// [`MinSimilarity`].
+    pub fn find_one_similar(&self, query: &str, min_similarity: f64) -> Option<(&str, u32, &V)> {
+        let bound = MinSimilarity::with_len(self.metric.len(query), min_similarity);
+        self.find_one_within(query, &bound)
+    }
+
+    /// Find best match in BK-tree within the bound for query
+    pub fn find_one_within(&self, query: &str, bound: &MinSimilarity) -> Option<(&str, u32, &V)> {
         let tree = self.tree.as_ref()?;
-        tree.find_one(query, max_edits.unwrap_or(u32::MAX))
+        tree.find_one(
+            &self.metric.query(query),
+            bound.bound()?,
+            &|key, distance| bound.accepts(distance, self.metric.len(key)),
+        )
+    }
+
+    /// Find all matches in BK-tree within max_edits of query
+    ///
+    /// Sorted by distance and then key
+    pub fn find_all(&self, query: &str, max_edits: u32) -> Vec<(&str, u32, &V)> {
+        let mut result = Vec::new();
+        if let Some(tree) = self.tree.as_ref() {
+            tree.find_all(&self.metric.query(query), max_edits, &mut result);
+        }
+        result.sort_unstable_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(b.0)));
+        result
+    }
+
+    /// Find the k best matches in BK-tree for query
+    ///
+    /// Sorted by distance and then key
+    pub fn find_k(&self, query: &str, k: usize, max_edits: Option<u32>) -> Vec<(&str, u32, &V)> {
+        // Nothing would be pruned with an empty heap
+        if k == 0 {
+            return Vec::new();
+        }
+        let mut heap = BoundedHeap::new(k);
+        if let Some(tree) = self.tree.as_ref() {
+            tree.find_k(
+                &self.metric.query(query),
+                &mut heap,
+                max_edits.unwrap_or(u32::MAX),
+            );
+        }
+        heap.into_sorted_vec()
+            .into_iter()
+            .map(|Keyed((distance, key), value)| (key, distance, value))
+            .collect()
+    }
+
+    /// Iterate over all keys and values in the BK-tree
+    pub fn iter<'a>(&'a self) -> Box<dyn Iterator<Item = (&'a str, &'a V)> + 'a> {
+        Box::new(self.tree.iter().flat_map(|t| t.iter()))
     }
 }
 
-impl Extend<String> for BKTree {
-    fn extend<I: IntoIterator<Item = String>>(&mut self, iter: I) {
-        for item in iter {
-            self.insert(item);
+impl<V, M: Metric> Extend<(String, V)> for BKTreeMap<V, M> {
+    fn extend<I: IntoIterator<Item = (String, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
         }
     }
 }
 
-impl FromIterator<String> for BKTree {
-    fn from_iter<I: IntoIterator<Item = String>>(iter: I) -> Self {
+impl<V> FromIterator<(String, V)> for BKTreeMap<V> {
+    fn from_iter<I: IntoIterator<Item = (String, V)>>(iter: I) -> Self {
         let mut tree = Self::new();
         tree.extend(iter);
         tree
     }
 }
 
-impl<'a> IntoIterator for &'a Tree {
-    type Item = &'a str;
-    type IntoIter = Box<dyn Iterator<Item = &'a str> + 'a>;
+impl<'a, V, M: Metric> IntoIterator for &'a BKTreeMap<V, M> {
+    type Item = (&'a str, &'a V);
+    type IntoIter = Box<dyn Iterator<Item = (&'a str, &'a V)> + 'a>;
 
     fn into_iter(self) -> Self::IntoIter {
         self.iter()
     }
 }
 
-impl Tree {
+/// BK-tree storing the strings to search against
+///
+/// Searches use the triangle inequality of the metric to limit the distance
+/// calculations needed.
+#[derive(Debug, Default, Clone)]
+pub struct BKTree<M = Levenshtein>(BKTreeMap<(), M>);
+
+impl BKTree {
+    /// Create an empty BK-tree using the Levenshtein distance
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Deserialize a BK-tree using the Levenshtein distance from bytes
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        Self::from_bytes_with_metric(bytes, Levenshtein)
+    }
+
+    /// Metric and segmentation stored in bytes created by to_bytes
+    ///
+    /// The metric is None if it is not built into the crate, and bytes of
+    /// versions before 3 are taken to use the Levenshtein distance.
+    pub fn stored_metric(
+        bytes: &[u8],
+    ) -> Result<(Option<BuiltinMetric>, Segmentation), DecodeError> {
+        let mut reader = Reader::new(bytes, serialize::BKTREE)?;
+        if reader.version() < 3 {
+            return Ok((Some(BuiltinMetric::Levenshtein), Segmentation::Codepoints));
+        }
+        let metric = BuiltinMetric::from_code(reader.u8()?)?;
+        let segmentation = Segmentation::from_code(reader.u8()?).ok_or(DecodeError::Invalid)?;
+        Ok((metric, segmentation))
+    }
+}
+
+impl<M: Metric> BKTree<M> {
+    /// Create an empty BK-tree using metric
+    pub fn with_metric(metric: M) -> Self {
+        Self(BKTreeMap::with_metric(metric))
+    }
+
+    /// Metric used by the BK-tree
+    pub fn metric(&self) -> &M {
+        self.0.metric()
+    }
+
+    /// Insert value into the BK-tree
+    pub fn insert(&mut self, value: String) {
+        self.0.insert(value, ());
+    }
+
+    /// Remove value from the BK-tree, returning whether it was present
+    ///
+    /// Removed values are marked as deleted, and subtrees are rebuilt once
+    /// more than half of their values have been removed.
+    pub fn remove(&mut self, value: &str) -> bool {
+        self.0.remove(value).is_some()
+    }
+
+    /// Get the stored value at zero distance from value
+    pub fn get(&self, value: &str) -> Option<&str> {
+        self.0.get(value).map(|x| x.0)
+    }
+
+    /// Whether the BK-tree contains value
+    pub fn contains(&self, value: &str) -> bool {
+        self.0.contains(value)
+    }
+
+    /// All values in the BK-tree
+    pub fn values(&self) -> Vec<&str> {
+        self.iter().collect()
+    }
+
+    /// Find best match in BK-tree for query
+    pub fn find_one(&self, query: &str, max_edits: Option<u32>) -> Option<(&str, u32)> {
+        self.0.find_one(query, max_edits).map(|x| (x.0, x.1))
+    }
+
+    /// Find best match in BK-tree with at least min_similarity to query
+    pub fn find_one_similar(&self, query: &str, min_similarity: f64) -> Option<(&str, u32)> {
+        let result = self.0.find_one_similar(query, min_similarity);
+        result.map(|x| (x.0, x.1))
+    }
+
+    /// Find best match in BK-tree within the bound for query
+    pub fn find_one_within(&self, query: &str, bound: &MinSimilarity) -> Option<(&str, u32)> {
+        self.0.find_one_within(query, bound).map(|x| (x.0, x.1))
+    }
+
+    /// Find all matches in BK-tree within max_edits of query
+    ///
+    /// Sorted by distance and then value
+    pub fn find_all(&self, query: &str, max_edits: u32) -> Vec<(&str, u32)> {
+        let result = self.0.find_all(query, max_edits);
+        result.into_iter().map(|x| (x.0, x.1)).collect()
+    }
+
+    /// Find the k best matches in BK-tree for query
+    ///
+    /// Sorted by distance and then value
+    pub fn find_k(&self, query: &str, k: usize, max_edits: Option<u32>) -> Vec<(&str, u32)> {
+        let result = self.0.find_k(query, k, max_edits);
+        result.into_iter().map(|x| (x.0, x.1)).collect()
+    }
+
+    /// Iterate over all values in the BK-tree
     pub fn iter<'a>(&'a self) -> Box<dyn Iterator<Item = &'a str> + 'a> {
-        Box::new(once(self.value.as_str()).chain(self.children.iter().flat_map(|x| x.1.iter())))
+        Box::new(self.0.iter().map(|x| x.0))
+    }
+
+    /// Serialize the BK-tree to bytes
+    ///
+    /// The structure of the tree is stored with the distances, so loading
+    /// does not need to evaluate the metric. Only which built-in metric and
+    /// segmentation are used is stored, not other metrics themselves.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::new(serialize::BKTREE);
+        writer.u8(BuiltinMetric::code(self.metric().builtin()));
+        writer.u8(self.metric().segmentation().code());
+        match self.0.tree.as_ref() {
+            Some(tree) => {
+                writer.u8(1);
+                tree.write(&mut writer);
+            }
+            None => writer.u8(0),
+        }
+        writer.into_bytes()
+    }
+
+    /// Deserialize a BK-tree from bytes created by to_bytes with the same metric
+    ///
+    /// Returns [`DecodeError::Options`] if the bytes store another built-in
+    /// metric or segmentation. Other metrics cannot be told apart, so must be
+    /// the same as the one the tree was created with.
+    pub fn from_bytes_with_metric(bytes: &[u8], metric: M) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(bytes, serialize::BKTREE)?;
+        if reader.version() >= 3 {
+            let builtin = BuiltinMetric::from_code(reader.u8()?)?;
+            let segmentation = Segmentation::from_code(reader.u8()?).ok_or(DecodeError::Invalid)?;
+            if builtin != metric.builtin() || segmentation != metric.segmentation() {
+                return Err(DecodeError::Options);
+            }
+        }
+        let tree = match reader.u8()? {
+            0 => None,
+            1 => Some(Tree::read(&mut reader)?),
+            _ => return Err(DecodeError::Invalid),
+        };
+        reader.finish()?;
+        Ok(Self(BKTreeMap { tree, metric }))
     }
 }
 
-impl<'a> IntoIterator for &'a BKTree {
+impl<M: Metric> Extend<String> for BKTree<M> {
+    fn extend<I: IntoIterator<Item = String>>(&mut self, iter: I) {
+        for item in iter {
+            self.insert(item);
+        }
+    }
+}
+
+impl FromIterator<String> for BKTree {
+    fn from_iter<I: IntoIterator<Item = String>>(iter: I) -> Self {
+        let mut tree = Self::new();
+        tree.extend(iter);
+        tree
+    }
+}
+
+impl<'a, M: Metric> IntoIterator for &'a BKTree<M> {
     type Item = &'a str;
     type IntoIter = Box<dyn Iterator<Item = &'a str> + 'a>;
 
@@ -156,15 +680,14 @@ impl<'a> IntoIterator for &'a BKTree {
     }
 }
 
-impl BKTree {
-    pub fn iter<'a>(&'a self) -> Box<dyn Iterator<Item = &'a str> + 'a> {
-        Box::new(self.tree.iter().flatten())
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::levenshtein::levenshtein;
+    use crate::metric::{Hamming, Levenshtein, Osa};
+    use crate::segmentation::{Segmentation, Segmented};
+    use crate::similarity::similarity;
+    use crate::trie::Trie;
     use std::collections::HashSet;
 
     #[test]
@@ -208,4 +731,314 @@ mod tests {
         assert_eq!(tree.find_one("baz", None), Some(("bar", 1)));
         assert_eq!(tree.find_one("baz", Some(0)), None);
     }
+
+    #[test]
+    fn find_similar() {
+        let tree = BKTree::from_iter(["ab", "abcdefgh", "foo", "foobar", "ba"].map(String::from));
+        assert_eq!(tree.find_one_similar("ac", 0.5), Some(("ab", 1)));
+        assert_eq!(tree.find_one_similar("ac", 0.6), None);
+        assert_eq!(
+            tree.find_one_similar("abcdefx", 0.75),
+            Some(("abcdefgh", 2))
+        );
+        let bound = MinSimilarity::new("abcdefx", 0.75).with_max_edits(1);
+        assert_eq!(tree.find_one_within("abcdefx", &bound), None);
+        for min in [0.0, 0.3, 0.5, 0.7, 0.9, 1.0] {
+            for query in ["", "a", "fo", "abcdef", "fooba", "bar", "xyz"] {
+                let brute = tree
+                    .iter()
+                    .map(|x| (levenshtein(query, x), x))
+                    .filter(|&(d, x)| {
+                        similarity(d, query.chars().count(), x.chars().count()) >= min
+                    })
+                    .map(|x| x.0)
+                    .min();
+                assert_eq!(tree.find_one_similar(query, min).map(|x| x.1), brute);
+            }
+        }
+    }
+
+    #[test]
+    fn graphemes() {
+        let metric = Segmented::new(Levenshtein, Segmentation::Graphemes);
+        let mut tree = BKTree::with_metric(metric);
+        tree.extend(["cafe\u{301}", "cafe", "👍🏽"].map(String::from));
+        assert_eq!(
+            tree.find_one("cafa\u{301}", Some(1)),
+            Some(("cafe\u{301}", 1))
+        );
+        assert_eq!(tree.find_all("👍", 1), vec![("👍🏽", 1)]);
+        // Lengths are counted in clusters too
+        assert_eq!(
+            tree.find_one_similar("cafa\u{301}", 0.75),
+            Some(("cafe\u{301}", 1))
+        );
+        assert_eq!(tree.find_one_similar("cafa\u{301}", 0.8), None);
+    }
+
+    #[test]
+    fn find_all() {
+        let tree = BKTree::from_iter(["foo", "bar", "baz", "ba", "qux"].map(String::from));
+        assert_eq!(tree.find_all("baz", 0), vec![("baz", 0)]);
+        assert_eq!(
+            tree.find_all("bay", 1),
+            vec![("ba", 1), ("bar", 1), ("baz", 1)]
+        );
+        assert_eq!(tree.find_all("", 1), vec![]);
+    }
+
+    #[test]
+    fn find_k() {
+        let tree = BKTree::from_iter(["foo", "bar", "baz", "ba", "qux"].map(String::from));
+        assert_eq!(tree.find_k("baz", 0, None), vec![]);
+        assert_eq!(tree.find_k("baz", 1, None), vec![("baz", 0)]);
+        assert_eq!(
+            tree.find_k("baz", 3, None),
+            vec![("baz", 0), ("ba", 1), ("bar", 1)]
+        );
+        assert_eq!(tree.find_k("bay", 2, None), vec![("ba", 1), ("bar", 1)]);
+        assert_eq!(tree.find_k("bay", 10, Some(1)).len(), 3);
+        assert_eq!(tree.find_k("bay", 10, None).len(), 5);
+
+        // No distances are computed for no matches
+        let mut tree = BKTree::with_metric(Failing(usize::MAX.into()));
+        tree.extend(["foo", "bar", "baz"].map(String::from));
+        tree.metric().0.set(1);
+        assert_eq!(tree.find_k("baz", 0, None), vec![]);
+        assert_eq!(tree.metric().0.get(), 1);
+    }
+
+    #[test]
+    fn find_osa() {
+        let mut tree = BKTree::with_metric(Osa);
+        tree.extend(["the", "tea", "then"].map(String::from));
+        assert!(tree.contains("the"));
+        assert_eq!(tree.find_k("teh", 2, None), vec![("tea", 1), ("the", 1)]);
+    }
+
+    #[test]
+    fn find_hamming() {
+        let mut tree = BKTree::with_metric(Hamming);
+        tree.extend(["foo", "for", "bar", "fo"].map(String::from));
+        assert_eq!(tree.find_k("fox", 2, None), vec![("fo", 1), ("foo", 1)]);
+        assert_eq!(tree.find_one("abc", Some(1)), None);
+    }
+
+    #[test]
+    fn remove() {
+        let mut tree = BKTree::from_iter(["foo", "bar", "baz", "", "qux"].map(String::from));
+        assert!(!tree.remove("quux"));
+        // Root
+        assert!(tree.remove("foo"));
+        assert!(!tree.remove("foo"));
+        assert!(!tree.contains("foo"));
+        assert_eq!(tree.find_one("foo", Some(2)), None);
+        assert_eq!(tree.find_one("fo", None), Some(("", 2)));
+        assert_eq!(
+            tree.iter().collect::<HashSet<_>>(),
+            HashSet::from(["bar", "baz", "", "qux"])
+        );
+
+        assert!(tree.remove(""));
+        assert!(!tree.contains(""));
+        assert_eq!(tree.find_k("", 10, None).len(), 3);
+
+        tree.insert("foo".to_string());
+        assert!(tree.contains("foo"));
+        for value in ["bar", "baz", "qux", "foo"] {
+            assert!(tree.remove(value));
+        }
+        assert_eq!(tree.iter().count(), 0);
+        assert!(tree.0.tree.is_none());
+        assert_eq!(tree.find_one("foo", None), None);
+
+        tree.insert("".to_string());
+        assert_eq!(tree.values(), vec![""]);
+    }
+
+    #[test]
+    fn remove_rebuild() {
+        let values: Vec<String> = (0..200).map(|i| format!("{i:x}")).collect();
+        let mut tree = BKTree::from_iter(values.iter().cloned());
+        for value in values.iter().step_by(3) {
+            assert!(tree.remove(value));
+        }
+        let root = tree.0.tree.as_ref().unwrap();
+        assert_eq!(root.len, 200 - 67);
+        assert!(root.size < 200);
+        for (i, value) in values.iter().enumerate() {
+            assert_eq!(tree.contains(value), i % 3 != 0);
+            assert_eq!(tree.find_one(value, Some(0)).is_some(), i % 3 != 0);
+        }
+    }
+
+    /// Levenshtein distance failing once a number of calls have been made
+    #[derive(Clone)]
+    struct Failing(std::cell::Cell<usize>);
+
+    impl Metric for Failing {
+        fn distance(&self, a: &str, b: &str) -> u32 {
+            match self.0.get() {
+                0 => u32::MAX,
+                n => {
+                    self.0.set(n - 1);
+                    levenshtein(a, b)
+                }
+            }
+        }
+
+        fn failed(&self) -> bool {
+            self.0.get() == 0
+        }
+    }
+
+    #[test]
+    fn failing_metric() {
+        let values: Vec<String> = (0..100).map(|i| format!("{i:x}")).collect();
+        let mut tree = BKTree::with_metric(Failing(usize::MAX.into()));
+        tree.extend(values.iter().cloned());
+        let found = |tree: &BKTree<Failing>| {
+            tree.metric().0.set(usize::MAX);
+            let root = tree.0.tree.as_ref().unwrap();
+            assert_eq!(root.len, root.iter().count());
+            let found = tree.find_all("1f", 1);
+            (
+                root.len,
+                root.size,
+                found
+                    .into_iter()
+                    .map(|x| x.0.to_string())
+                    .collect::<Vec<_>>(),
+            )
+        };
+        let expected = found(&tree);
+        assert_eq!(expected.0, 100);
+
+        // Fails partway down the path of the key
+        for key in ["1g", "1f"] {
+            let path = tree.0.tree.as_ref().unwrap().path(key, tree.metric());
+            for calls in 0..path.len() {
+                tree.metric().0.set(calls);
+                tree.insert(key.to_string());
+                assert_eq!(found(&tree), expected);
+                tree.metric().0.set(calls);
+                assert!(!tree.remove(key));
+                assert_eq!(found(&tree), expected);
+            }
+        }
+
+        // Fails while rebuilding, keeping the removed key as a tombstone
+        for value in values.iter().skip(50) {
+            assert!(tree.remove(value));
+        }
+        let mut removed = 50;
+        for value in &values[..50] {
+            let (len, size, _) = found(&tree);
+            let mut rebuilt = tree.clone();
+            assert!(rebuilt.remove(value));
+            removed += 1;
+            if found(&rebuilt).1 == size {
+                tree = rebuilt;
+                continue;
+            }
+            let path = tree.0.tree.as_ref().unwrap().path(value, tree.metric());
+            tree.metric().0.set(path.len() + 1);
+            assert!(tree.remove(value));
+            let (len_after, size_after, _) = found(&tree);
+            assert_eq!((len_after, size_after), (len - 1, size));
+            break;
+        }
+        assert!(removed < 100);
+        for (i, value) in values.iter().enumerate() {
+            assert_eq!(tree.contains(value), i >= removed - 50 && i < 50);
+        }
+    }
+
+    #[test]
+    fn bytes() {
+        let mut tree = BKTree::from_iter(["foo", "bar", "baz", "", "qux"].map(String::from));
+        tree.remove("bar");
+        let bytes = tree.to_bytes();
+        let loaded = BKTree::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded.values(), tree.values());
+        assert_eq!(loaded.to_bytes(), bytes);
+        assert!(!loaded.contains("bar"));
+        assert_eq!(loaded.find_k("ba", 2, None), vec![("baz", 1), ("", 2)]);
+        let root = loaded.0.tree.as_ref().unwrap();
+        assert_eq!((root.len, root.size), (4, 5));
+
+        assert_eq!(
+            BKTree::from_bytes(&bytes[..bytes.len() - 1]).err(),
+            Some(DecodeError::Truncated)
+        );
+        assert_eq!(
+            BKTree::from_bytes(&Trie::new().to_bytes()).err(),
+            Some(DecodeError::Header)
+        );
+        let empty = BKTree::new().to_bytes();
+        assert_eq!(BKTree::from_bytes(&empty).unwrap().iter().count(), 0);
+
+        // Bytes of version 2 do not store the metric
+        let mut old = bytes.clone();
+        old.drain(serialize::HEADER_LEN..serialize::HEADER_LEN + 2);
+        old[serialize::HEADER_LEN - 1] = 2;
+        assert_eq!(BKTree::from_bytes(&old).unwrap().values(), tree.values());
+        let stored = BKTree::stored_metric(&old).unwrap();
+        assert_eq!(
+            stored,
+            (Some(BuiltinMetric::Levenshtein), Segmentation::Codepoints)
+        );
+    }
+
+    #[test]
+    fn bytes_metric() {
+        let values = ["foo", "bar", "baz"].map(String::from);
+        let metric = Segmented::new(Osa, Segmentation::Graphemes);
+        let mut tree = BKTree::with_metric(metric.clone());
+        tree.extend(values.clone());
+        let bytes = tree.to_bytes();
+        assert_eq!(
+            BKTree::stored_metric(&bytes),
+            Ok((Some(BuiltinMetric::Osa), Segmentation::Graphemes))
+        );
+        let loaded = BKTree::from_bytes_with_metric(&bytes, metric).unwrap();
+        assert_eq!(loaded.values(), tree.values());
+        assert_eq!(BKTree::from_bytes(&bytes).err(), Some(DecodeError::Options));
+        let codepoints = Segmented::new(Osa, Segmentation::Codepoints);
+        assert_eq!(
+            BKTree::from_bytes_with_metric(&bytes, codepoints).err(),
+            Some(DecodeError::Options)
+        );
+
+        // Other metrics are only stored as such
+        let mut tree = BKTree::with_metric(Failing(usize::MAX.into()));
+        tree.extend(values);
+        let bytes = tree.to_bytes();
+        assert_eq!(
+            BKTree::stored_metric(&bytes),
+            Ok((None, Segmentation::Codepoints))
+        );
+        let loaded = BKTree::from_bytes_with_metric(&bytes, Failing(usize::MAX.into()));
+        assert_eq!(loaded.unwrap().values(), tree.values());
+        assert_eq!(BKTree::from_bytes(&bytes).err(), Some(DecodeError::Options));
+    }
+
+    #[test]
+    fn map() {
+        let mut map = BKTreeMap::new();
+        assert_eq!(map.insert("foo".to_string(), 1), None);
+        assert_eq!(map.insert("bar".to_string(), 2), None);
+        assert_eq!(map.insert("foo".to_string(), 3), Some(1));
+        assert_eq!(map.get("foo"), Some(("foo", &3)));
+        assert_eq!(map.find_one("baz", None), Some(("bar", 1, &2)));
+        assert_eq!(map.find_all("fo", 1), vec![("foo", 1, &3)]);
+        assert_eq!(
+            map.find_k("baz", 2, None),
+            vec![("bar", 1, &2), ("foo", 3, &3)]
+        );
+        assert_eq!(map.remove("foo"), Some(3));
+        assert_eq!(map.remove("foo"), None);
+        assert_eq!(map.insert("foo".to_string(), 4), None);
+        assert_eq!(map.get("foo"), Some(("foo", &4)));
+    }
 }
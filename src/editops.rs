@@ -0,0 +1,319 @@
+//! Edit operations transforming one string into another
+//!
+//! An optimal alignment is found with Hirschberg's algorithm in linear space,
+//! splitting the strings where the distances of the two halves add up to the
+//! least. The rows of distances come from the bitvector automaton, so that
+//! long strings are aligned quickly too.
+
+use crate::levenshtein::{AutomatonState, LevenshteinAutomaton};
+
+/// Kind of an edit operation
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum EditKind {
+    /// Chars are the same
+    Equal,
+    /// Char of the source is substituted with a char of the destination
+    Replace,
+    /// Char of the destination is inserted
+    Insert,
+    /// Char of the source is deleted
+    Delete,
+}
+
+impl EditKind {
+    /// Name of the kind, as used by difflib
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Equal => "equal",
+            Self::Replace => "replace",
+            Self::Insert => "insert",
+            Self::Delete => "delete",
+        }
+    }
+}
+
+/// Edit operation at char src of the source and char dest of the destination
+///
+/// Deletes remove the char at src and inserts add the char at dest before the
+/// char at src, while replaces substitute the char at src with that at dest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Editop {
+    /// Kind of operation
+    pub kind: EditKind,
+    /// Position in the source, in chars
+    pub src: usize,
+    /// Position in the destination, in chars
+    pub dest: usize,
+}
+
+/// Edit operation turning chars src_start..src_end of the source into chars
+/// dest_start..dest_end of the destination
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Opcode {
+    /// Kind of operation
+    pub kind: EditKind,
+    /// Start in the source, in chars
+    pub src_start: usize,
+    /// End in the source, in chars
+    pub src_end: usize,
+    /// Start in the destination, in chars
+    pub dest_start: usize,
+    /// End in the destination, in chars
+    pub dest_end: usize,
+}
+
+// Largest number of cells to align with the full table rather than splitting
+const TABLE: usize = 1 << 12;
+
+/// Append the alignment of a with b, at positions src and dest, to ops
+fn align(a: &[char], b: &[char], src: usize, dest: usize, ops: &mut Vec<Editop>) {
+    let op = |kind, src, dest| Editop { kind, src, dest };
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let (a, b) = (&a[prefix..], &b[prefix..]);
+    let suffix = a
+        .iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (a, b) = (&a[..a.len() - suffix], &b[..b.len() - suffix]);
+    ops.extend((0..prefix).map(|i| op(EditKind::Equal, src + i, dest + i)));
+    let (src, dest) = (src + prefix, dest + prefix);
+
+    if a.is_empty() || b.is_empty() {
+        ops.extend((0..a.len()).map(|i| op(EditKind::Delete, src + i, dest)));
+        ops.extend((0..b.len()).map(|j| op(EditKind::Insert, src, dest + j)));
+    } else if a.len() == 1 || a.len().saturating_mul(b.len()) <= TABLE {
+        align_table(a, b, src, dest, ops);
+    } else {
+        // Distances from the first half of a to each prefix of b, and from
+        // the second half to each suffix
+        let mid = a.len() / 2;
+        let forward: String = b.iter().collect();
+        let forward = LevenshteinAutomaton::new(&forward);
+        let mut state = forward.start();
+        for &c in &a[..mid] {
+            state.step_mut(c);
+        }
+        let forward = state.distances();
+        let reverse: String = b.iter().rev().collect();
+        let reverse = LevenshteinAutomaton::new(&reverse);
+        let mut state = reverse.start();
+        for &c in a[mid..].iter().rev() {
+            state.step_mut(c);
+        }
+        let reverse = state.distances();
+        let split = (0..=b.len())
+            .min_by_key(|&j| forward[j] + reverse[b.len() - j])
+            .unwrap();
+        align(&a[..mid], &b[..split], src, dest, ops);
+        align(&a[mid..], &b[split..], src + mid, dest + split, ops);
+    }
+
+    let (src, dest) = (src + a.len(), dest + b.len());
+    ops.extend((0..suffix).map(|i| op(EditKind::Equal, src + i, dest + i)));
+}
+
+/// Append the alignment of a with b from the full table of distances
+fn align_table(a: &[char], b: &[char], src: usize, dest: usize, ops: &mut Vec<Editop>) {
+    let width = b.len() + 1;
+    let mut table = vec![0u32; (a.len() + 1) * width];
+    for (j, x) in table[..width].iter_mut().enumerate() {
+        *x = j as u32;
+    }
+    for i in 1..=a.len() {
+        table[i * width] = i as u32;
+        for j in 1..width {
+            let sub = table[(i - 1) * width + j - 1] + (a[i - 1] != b[j - 1]) as u32;
+            let del = table[(i - 1) * width + j] + 1;
+            let ins = table[i * width + j - 1] + 1;
+            table[i * width + j] = sub.min(del).min(ins);
+        }
+    }
+
+    // Trace back from the end, preferring substitutions
+    let start = ops.len();
+    let (mut i, mut j) = (a.len(), b.len());
+    while i > 0 || j > 0 {
+        let distance = table[i * width + j];
+        let kind = if i > 0
+            && j > 0
+            && table[(i - 1) * width + j - 1] + (a[i - 1] != b[j - 1]) as u32 == distance
+        {
+            i -= 1;
+            j -= 1;
+            if a[i] == b[j] {
+                EditKind::Equal
+            } else {
+                EditKind::Replace
+            }
+        } else if i > 0 && table[(i - 1) * width + j] + 1 == distance {
+            i -= 1;
+            EditKind::Delete
+        } else {
+            j -= 1;
+            EditKind::Insert
+        };
+        ops.push(Editop {
+            kind,
+            src: src + i,
+            dest: dest + j,
+        });
+    }
+    ops[start..].reverse();
+}
+
+/// Operations for every char aligning a with b, including equal chars
+fn alignment(a: &str, b: &str) -> Vec<Editop> {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut ops = Vec::with_capacity(a.len().max(b.len()));
+    align(&a, &b, 0, 0, &mut ops);
+    ops
+}
+
+/// Edit operations transforming a into b, in order
+///
+/// The operations are as few as the Levenshtein distance between a and b.
+pub fn editops(a: &str, b: &str) -> Vec<Editop> {
+    let mut ops = alignment(a, b);
+    ops.retain(|x| x.kind != EditKind::Equal);
+    ops
+}
+
+/// Ranges of a and b that are equal or edited, transforming a into b
+///
+/// The opcodes cover both strings in order, with consecutive operations of
+/// the same kind grouped together.
+pub fn opcodes(a: &str, b: &str) -> Vec<Opcode> {
+    let mut opcodes: Vec<Opcode> = Vec::new();
+    for Editop { kind, src, dest } in alignment(a, b) {
+        let src_end = src + (kind != EditKind::Insert) as usize;
+        let dest_end = dest + (kind != EditKind::Delete) as usize;
+        match opcodes.last_mut() {
+            Some(last) if last.kind == kind => {
+                last.src_end = src_end;
+                last.dest_end = dest_end;
+            }
+            _ => opcodes.push(Opcode {
+                kind,
+                src_start: src,
+                src_end,
+                dest_start: dest,
+                dest_end,
+            }),
+        }
+    }
+    opcodes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::levenshtein::levenshtein;
+
+    /// Apply ops to a, checking the positions in b along the way
+    fn apply(a: &str, b: &str, ops: &[Editop]) -> String {
+        let a: Vec<char> = a.chars().collect();
+        let b: Vec<char> = b.chars().collect();
+        let mut result = Vec::new();
+        let mut i = 0;
+        for op in ops {
+            assert!(op.src >= i);
+            result.extend(&a[i..op.src]);
+            i = op.src;
+            assert_eq!(result.len(), op.dest);
+            match op.kind {
+                EditKind::Delete => i += 1,
+                EditKind::Replace => {
+                    assert_ne!(a[i], b[op.dest]);
+                    result.push(b[op.dest]);
+                    i += 1;
+                }
+                EditKind::Insert => result.push(b[op.dest]),
+                EditKind::Equal => unreachable!(),
+            }
+        }
+        result.extend(&a[i..]);
+        result.into_iter().collect()
+    }
+
+    #[test]
+    fn ops() {
+        let op = |kind, src, dest| Editop { kind, src, dest };
+        assert_eq!(
+            editops("kitten", "sitting"),
+            vec![
+                op(EditKind::Replace, 0, 0),
+                op(EditKind::Replace, 4, 4),
+                op(EditKind::Insert, 6, 6),
+            ]
+        );
+        assert_eq!(editops("", "ab").len(), 2);
+        assert_eq!(
+            editops("ab", ""),
+            vec![op(EditKind::Delete, 0, 0), op(EditKind::Delete, 1, 0)]
+        );
+        assert_eq!(editops("same", "same"), vec![]);
+
+        let long = "the quick brown fox jumps over the lazy dog ".repeat(5);
+        let other = long.replace("quick", "quack").replace("lazy ", "") + "!";
+        let pairs = [
+            ("", ""),
+            ("kitten", "sitting"),
+            ("café", "coffee"),
+            ("abcabcabc", "cbacba"),
+            ("ab", "ba"),
+            (long.as_str(), other.as_str()),
+            (&long[..150], &other[40..]),
+            (&long[..100], "fox"),
+        ];
+        for (a, b) in pairs {
+            for (a, b) in [(a, b), (b, a)] {
+                let ops = editops(a, b);
+                assert_eq!(ops.len() as u32, levenshtein(a, b), "{a} {b}");
+                assert_eq!(apply(a, b, &ops), b);
+            }
+        }
+    }
+
+    #[test]
+    fn codes() {
+        let code = |kind, src_start, src_end, dest_start, dest_end| Opcode {
+            kind,
+            src_start,
+            src_end,
+            dest_start,
+            dest_end,
+        };
+        assert_eq!(
+            opcodes("kitten", "sitting"),
+            vec![
+                code(EditKind::Replace, 0, 1, 0, 1),
+                code(EditKind::Equal, 1, 4, 1, 4),
+                code(EditKind::Replace, 4, 5, 4, 5),
+                code(EditKind::Equal, 5, 6, 5, 6),
+                code(EditKind::Insert, 6, 6, 6, 7),
+            ]
+        );
+        assert_eq!(opcodes("", ""), vec![]);
+        assert_eq!(opcodes("abc", ""), vec![code(EditKind::Delete, 0, 3, 0, 0)]);
+
+        let a = "lorem ipsum dolor sit amet ".repeat(10);
+        let b = a.replace("dolor", "color").replace("sit ", "");
+        let codes = opcodes(&a, &b);
+        // Opcodes cover both strings without gaps
+        assert_eq!((codes[0].src_start, codes[0].dest_start), (0, 0));
+        assert!(codes.windows(2).all(|x| x[0].src_end == x[1].src_start
+            && x[0].dest_end == x[1].dest_start
+            && x[0].kind != x[1].kind));
+        let last = codes.last().unwrap();
+        assert_eq!((last.src_end, last.dest_end), (a.len(), b.len()));
+        let changed: usize = codes
+            .iter()
+            .filter(|x| x.kind != EditKind::Equal)
+            .map(|x| (x.src_end - x.src_start).max(x.dest_end - x.dest_start))
+            .sum();
+        assert_eq!(changed as u32, levenshtein(&a, &b));
+    }
+}
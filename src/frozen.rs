@@ -0,0 +1,641 @@
+//! Immutable minimal automaton for searching a static set of strings
+//!
+//! A [`FrozenTrie`] is a minimal acyclic automaton (DAWG), sharing suffixes as
+//! well as prefixes. It is stored as a flat array of nodes in bytes: after the
+//! header, the offset of the root and the number of values, each node is a u32
+//! of the number of edges and whether it is final, followed by the edges sorted
+//! by char as u32 pairs of the char and the offset of the target node. Targets
+//! always precede the node, as nodes are written once their suffixes are.
+//! Offsets being u32, nodes must start within the first 4GiB of the bytes,
+//! and there can be at most u32::MAX values.
+//!
+//! As the bytes are used directly, a frozen trie saved to a file can be opened
+//! by memory mapping it with the `mmap` feature, without deserializing it:
+//! opening reads the nodes once to check their structure, after which they are
+//! searched in place.
+
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use crate::levenshtein::{AutomatonState, LevenshteinAutomaton};
+use crate::segmentation::Segmentation;
+use crate::serialize::{self, DecodeError, Reader, Writer};
+use crate::trie::Trie;
+
+const ROOT: usize = serialize::HEADER_LEN;
+const LEN: usize = ROOT + 4;
+const NODES: usize = LEN + 4;
+
+fn read_u32(data: &[u8], pos: usize) -> u32 {
+    u32::from_le_bytes(data[pos..pos + 4].try_into().unwrap())
+}
+
+/// Error building a frozen trie
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BuildError {
+    /// Values are not sorted, with the first value out of order
+    Unsorted(String),
+    /// Nodes or values are too many for offsets and counts of u32
+    TooLarge,
+    /// The trie has grapheme segmentation or a normalizer, which frozen tries
+    /// do not support
+    Options,
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Unsorted(value) => write!(f, "values not sorted: {value}"),
+            Self::TooLarge => write!(f, "frozen trie too large"),
+            Self::Options => write!(
+                f,
+                "frozen tries do not support grapheme segmentation or normalizers"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for BuildError {}
+
+#[derive(Debug, Clone, Copy)]
+struct Node<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Node<'a> {
+    /// Node at pos, checking that it lies within data
+    fn new(data: &'a [u8], pos: usize) -> Result<Self, DecodeError> {
+        let header = data.get(pos..pos + 4).ok_or(DecodeError::Truncated)?;
+        let len = (u32::from_le_bytes(header.try_into().unwrap()) >> 1) as usize;
+        let end = len.checked_mul(8).and_then(|x| x.checked_add(pos + 4));
+        if end.ok_or(DecodeError::Invalid)? > data.len() {
+            return Err(DecodeError::Truncated);
+        }
+        Ok(Self { data, pos })
+    }
+
+    fn is_final(&self) -> bool {
+        read_u32(self.data, self.pos) & 1 == 1
+    }
+
+    fn len(&self) -> usize {
+        (read_u32(self.data, self.pos) >> 1) as usize
+    }
+
+    /// Edge i with the node it leads to
+    fn edge(&self, i: usize) -> (char, Node<'a>) {
+        let pos = self.pos + 4 + 8 * i;
+        // Checked by from_bytes
+        let value = char::from_u32(read_u32(self.data, pos)).unwrap();
+        let node = Node {
+            data: self.data,
+            pos: read_u32(self.data, pos + 4) as usize,
+        };
+        (value, node)
+    }
+
+    fn edges(self) -> impl Iterator<Item = (char, Node<'a>)> {
+        (0..self.len()).map(move |i| self.edge(i))
+    }
+
+    fn next(&self, value: char) -> Option<Node<'a>> {
+        let (mut lo, mut hi) = (0, self.len());
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            let (c, node) = self.edge(mid);
+            match c.cmp(&value) {
+                std::cmp::Ordering::Less => lo = mid + 1,
+                std::cmp::Ordering::Greater => hi = mid,
+                std::cmp::Ordering::Equal => return Some(node),
+            }
+        }
+        None
+    }
+}
+
+/// Edges of a node with the offset of the target node
+type Edges = Vec<(char, u32)>;
+
+/// Builder adding sorted values one at a time, minimizing as it goes
+struct Builder {
+    data: Vec<u8>,
+    // Nodes already written, to share identical suffixes
+    register: HashMap<(bool, Edges), u32>,
+    // Nodes on the path of the previous value, with the char leading to them
+    stack: Vec<(char, bool, Edges)>,
+    previous: Option<String>,
+    len: u32,
+    // Largest offset of a node
+    max_offset: usize,
+}
+
+impl Builder {
+    fn new() -> Self {
+        let mut data = Writer::new(serialize::FROZEN).into_bytes();
+        data.resize(NODES, 0);
+        Self {
+            data,
+            register: HashMap::new(),
+            stack: vec![('\0', false, Vec::new())],
+            previous: None,
+            len: 0,
+            max_offset: u32::MAX as usize,
+        }
+    }
+
+    fn push(&mut self, value: &str) -> Result<(), BuildError> {
+        let common = match self.previous.as_deref() {
+            Some(previous) if previous == value => return Ok(()),
+            Some(previous) if previous > value => {
+                return Err(BuildError::Unsorted(value.to_string()))
+            }
+            Some(previous) => previous
+                .chars()
+                .zip(value.chars())
+                .take_while(|(a, b)| a == b)
+                .count(),
+            None => 0,
+        };
+        self.len = self.len.checked_add(1).ok_or(BuildError::TooLarge)?;
+        self.freeze(common + 1)?;
+        for c in value.chars().skip(common) {
+            self.stack.push((c, false, Vec::new()));
+        }
+        self.stack.last_mut().unwrap().1 = true;
+        self.previous = Some(value.to_string());
+        Ok(())
+    }
+
+    /// Write the nodes deeper than depth on the path of the previous value
+    fn freeze(&mut self, depth: usize) -> Result<(), BuildError> {
+        while self.stack.len() > depth {
+            let (c, is_final, edges) = self.stack.pop().unwrap();
+            let target = self.write(is_final, edges)?;
+            self.stack.last_mut().unwrap().2.push((c, target));
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, is_final: bool, edges: Edges) -> Result<u32, BuildError> {
+        match self.register.entry((is_final, edges)) {
+            Entry::Occupied(entry) => Ok(*entry.get()),
+            Entry::Vacant(entry) => {
+                if self.data.len() > self.max_offset {
+                    return Err(BuildError::TooLarge);
+                }
+                let pos = self.data.len() as u32;
+                let (is_final, edges) = entry.key();
+                // Chars are below 2^21, so the number of edges fits with the flag
+                let header = (edges.len() as u32) << 1 | *is_final as u32;
+                self.data.extend(header.to_le_bytes());
+                for (c, target) in edges {
+                    self.data.extend((*c as u32).to_le_bytes());
+                    self.data.extend(target.to_le_bytes());
+                }
+                Ok(*entry.insert(pos))
+            }
+        }
+    }
+
+    fn finish(mut self) -> Result<Vec<u8>, BuildError> {
+        self.freeze(1)?;
+        let (_, is_final, edges) = self.stack.pop().unwrap();
+        let root = self.write(is_final, edges)?;
+        self.data[ROOT..LEN].copy_from_slice(&root.to_le_bytes());
+        self.data[LEN..NODES].copy_from_slice(&self.len.to_le_bytes());
+        Ok(self.data)
+    }
+}
+
+/// Immutable trie storing the strings to search against, with shared suffixes
+///
+/// Built from sorted values, and searched with an automaton in the same way as
+/// a [`Trie`]. Values are not stored, so results are collected as the trie is
+/// traversed.
+#[derive(Debug, Clone)]
+pub struct FrozenTrie<D = Vec<u8>> {
+    data: D,
+}
+
+impl FrozenTrie {
+    /// Create a frozen trie from values in sorted order, skipping duplicates
+    ///
+    /// Fails if the values are not sorted, or if the nodes do not fit within
+    /// the 4GiB that offsets can address.
+    pub fn from_sorted_iter<I, S>(iter: I) -> Result<Self, BuildError>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<str>,
+    {
+        let mut builder = Builder::new();
+        for value in iter {
+            builder.push(value.as_ref())?;
+        }
+        Ok(Self {
+            data: builder.finish()?,
+        })
+    }
+}
+
+impl TryFrom<&Trie> for FrozenTrie {
+    type Error = BuildError;
+
+    /// Fails with [`BuildError::Options`] if the trie has grapheme
+    /// segmentation or a normalizer, as queries would not be searched the same
+    fn try_from(trie: &Trie) -> Result<Self, BuildError> {
+        if trie.segmentation() != Segmentation::Codepoints || !trie.normalizer().is_none() {
+            return Err(BuildError::Options);
+        }
+        let mut values = trie.values();
+        values.sort_unstable();
+        Self::from_sorted_iter(values)
+    }
+}
+
+#[cfg(feature = "mmap")]
+impl FrozenTrie<memmap2::Mmap> {
+    /// Open a frozen trie saved to a file by memory mapping it
+    ///
+    /// The nodes are checked once by reading the file, after which its pages
+    /// stay in the page cache, shared between processes with the same file
+    /// open. The file must not be modified while it is open.
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = std::fs::File::open(path)?;
+        // Safety: the mapping is only read, and the file is not to be modified
+        let mmap = unsafe { memmap2::Mmap::map(&file)? };
+        Self::from_bytes(mmap).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+    }
+}
+
+impl<D: AsRef<[u8]>> FrozenTrie<D> {
+    /// Use bytes created by to_bytes as a frozen trie
+    ///
+    /// The bytes are used without copying them, but every node is checked
+    /// once, reading all of the bytes and allocating a bit for each, so that
+    /// searches never read corrupt nodes.
+    pub fn from_bytes(data: D) -> Result<Self, DecodeError> {
+        let bytes = data.as_ref();
+        Reader::new(bytes, serialize::FROZEN)?;
+        if bytes.len() < NODES {
+            return Err(DecodeError::Truncated);
+        }
+        let root = read_u32(bytes, ROOT) as usize;
+        if root < NODES {
+            return Err(DecodeError::Invalid);
+        }
+        Node::new(bytes, root)?;
+        Self::check(bytes)?;
+        Ok(Self { data })
+    }
+
+    /// Check the structure of every node
+    fn check(bytes: &[u8]) -> Result<(), DecodeError> {
+        // Bitset of the positions where nodes start
+        let mut starts = vec![0u64; bytes.len() / 64 + 1];
+        let mut pos = NODES;
+        while pos < bytes.len() {
+            let end = Node::new(bytes, pos)?.len() * 8 + pos + 4;
+            let mut previous = None;
+            for edge in (pos + 4..end).step_by(8) {
+                let value = char::from_u32(read_u32(bytes, edge)).ok_or(DecodeError::Invalid)?;
+                let target = read_u32(bytes, edge + 4) as usize;
+                // Edges are sorted and point to nodes written earlier
+                if previous >= Some(value)
+                    || target >= pos
+                    || starts[target / 64] & 1 << (target % 64) == 0
+                {
+                    return Err(DecodeError::Invalid);
+                }
+                previous = Some(value);
+            }
+            starts[pos / 64] |= 1 << (pos % 64);
+            pos = end;
+        }
+        let root = read_u32(bytes, ROOT) as usize;
+        if starts[root / 64] & 1 << (root % 64) == 0 {
+            return Err(DecodeError::Invalid);
+        }
+        Ok(())
+    }
+
+    /// Bytes of the frozen trie
+    pub fn as_bytes(&self) -> &[u8] {
+        self.data.as_ref()
+    }
+
+    /// Take the underlying bytes of the frozen trie
+    pub fn into_data(self) -> D {
+        self.data
+    }
+
+    /// Serialize the frozen trie to bytes
+    pub fn to_bytes(&self) -> Vec<u8> {
+        self.as_bytes().to_vec()
+    }
+
+    /// Save the frozen trie to a file
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        std::fs::write(path, self.as_bytes())
+    }
+
+    fn root(&self) -> Node<'_> {
+        let data = self.data.as_ref();
+        // Checked by from_bytes
+        Node {
+            data,
+            pos: read_u32(data, ROOT) as usize,
+        }
+    }
+
+    /// Number of values in the frozen trie
+    pub fn len(&self) -> usize {
+        read_u32(self.data.as_ref(), LEN) as usize
+    }
+
+    /// Whether the frozen trie is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Get the stored value equal to value
+    pub fn get<'a>(&self, value: &'a str) -> Option<&'a str> {
+        let mut node = self.root();
+        for c in value.chars() {
+            node = node.next(c)?;
+        }
+        node.is_final().then_some(value)
+    }
+
+    /// Whether the frozen trie contains value
+    pub fn contains(&self, value: &str) -> bool {
+        self.get(value).is_some()
+    }
+
+    /// All values in the frozen trie, in sorted order
+    pub fn values(&self) -> Vec<String> {
+        // Not reserving len, as the stored count is not checked by from_bytes
+        let mut result = Vec::new();
+        Self::values_node(self.root(), &mut String::new(), &mut result);
+        result
+    }
+
+    /// Find best match in frozen trie for query
+    ///
+    /// Ties are broken by the smallest value
+    pub fn find_one(&self, query: &str, max_edits: Option<u32>) -> Option<(String, u32)> {
+        let automaton = LevenshteinAutomaton::new(query);
+        self.find_one_with(&automaton.start(), max_edits)
+    }
+
+    /// Find best match in frozen trie for the query of an automaton
+    pub fn find_one_with(
+        &self,
+        start: &impl AutomatonState,
+        max_edits: Option<u32>,
+    ) -> Option<(String, u32)> {
+        let max_edits = max_edits.unwrap_or(u32::MAX);
+        Self::find_automaton(self.root(), start, max_edits, &mut String::new())
+    }
+
+    /// Find all matches in frozen trie within max_edits of query
+    ///
+    /// Sorted by distance and then value
+    pub fn find_all(&self, query: &str, max_edits: u32) -> Vec<(String, u32)> {
+        let automaton = LevenshteinAutomaton::new(query);
+        self.find_all_with(&automaton.start(), max_edits)
+    }
+
+    /// Find all matches in frozen trie within max_edits of the query of an automaton
+    pub fn find_all_with(&self, start: &impl AutomatonState, max_edits: u32) -> Vec<(String, u32)> {
+        let mut result = Vec::new();
+        let root = self.root();
+        Self::find_all_automaton(root, start, max_edits, &mut String::new(), &mut result);
+        // Traversal is in order of value, so a stable sort suffices
+        result.sort_by_key(|x| x.1);
+        result
+    }
+
+    fn values_node(node: Node, key: &mut String, result: &mut Vec<String>) {
+        if node.is_final() {
+            result.push(key.clone());
+        }
+        for (c, next) in node.edges() {
+            key.push(c);
+            Self::values_node(next, key, result);
+            key.pop();
+        }
+    }
+
+    fn find_automaton(
+        node: Node,
+        state: &impl AutomatonState,
+        max_edits: u32,
+        key: &mut String,
+    ) -> Option<(String, u32)> {
+        if !state.can_match(max_edits) {
+            return None;
+        }
+        let mut best = Some(state.distance())
+            .filter(|d| node.is_final() && *d <= max_edits)
+            .map(|d| (key.clone(), d));
+        for (c, next) in node.edges() {
+            // Only look for strictly better matches once one is found
+            let Some(max_edits) = best
+                .as_ref()
+                .map_or(Some(max_edits), |x| x.1.checked_sub(1))
+            else {
+                break;
+            };
+            key.push(c);
+            if let Some(found) = Self::find_automaton(next, &state.step(c), max_edits, key) {
+                best = Some(found);
+            }
+            key.pop();
+        }
+        best
+    }
+
+    fn find_all_automaton(
+        node: Node,
+        state: &impl AutomatonState,
+        max_edits: u32,
+        key: &mut String,
+        result: &mut Vec<(String, u32)>,
+    ) {
+        if !state.can_match(max_edits) {
+            return;
+        }
+        if node.is_final() {
+            let distance = state.distance();
+            if distance <= max_edits {
+                result.push((key.clone(), distance));
+            }
+        }
+        for (c, next) in node.edges() {
+            key.push(c);
+            Self::find_all_automaton(next, &state.step(c), max_edits, key, result);
+            key.pop();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::normalize::{Normalization, Normalizer};
+
+    #[test]
+    fn values() {
+        let frozen = FrozenTrie::from_sorted_iter(["", "bar", "baz", "foo", "foo"]).unwrap();
+        assert_eq!(frozen.len(), 4);
+        assert!(frozen.contains(""));
+        assert!(frozen.contains("baz"));
+        assert!(!frozen.contains("ba"));
+        assert_eq!(frozen.get("foo"), Some("foo"));
+        assert_eq!(frozen.values(), vec!["", "bar", "baz", "foo"]);
+
+        let empty = FrozenTrie::from_sorted_iter(Vec::<String>::new()).unwrap();
+        assert!(empty.is_empty());
+        assert!(!empty.contains(""));
+        assert_eq!(empty.find_one("foo", None), None);
+
+        assert_eq!(
+            FrozenTrie::from_sorted_iter(["foo", "bar"]).err(),
+            Some(BuildError::Unsorted("bar".to_string()))
+        );
+
+        // Nodes starting beyond the largest offset
+        let mut builder = Builder::new();
+        builder.max_offset = NODES + 4;
+        for value in ["ab", "c"] {
+            builder.push(value).unwrap();
+        }
+        assert_eq!(builder.finish().err(), Some(BuildError::TooLarge));
+        let mut builder = Builder::new();
+        builder.max_offset = NODES + 4;
+        builder.push("a").unwrap();
+        assert!(builder.finish().is_ok());
+    }
+
+    #[test]
+    fn minimal() {
+        let values = ["tap", "taps", "top", "tops"];
+        let frozen = FrozenTrie::from_sorted_iter(values).unwrap();
+        // Nodes after a and o are shared, for five nodes with five edges
+        let nodes = frozen.as_bytes().len() - NODES;
+        assert_eq!(nodes, 4 * 5 + 8 * 5);
+        assert_eq!(frozen.values(), values);
+    }
+
+    #[test]
+    fn find() {
+        let trie = Trie::from_iter(["foo", "bar", "baz", "ba", "fö"].map(String::from));
+        let frozen = FrozenTrie::try_from(&trie).unwrap();
+        assert_eq!(frozen.find_one("", Some(1)), None);
+        assert_eq!(frozen.find_one("baz", None), Some(("baz".to_string(), 0)));
+        assert_eq!(frozen.find_one("bay", None), Some(("ba".to_string(), 1)));
+        assert_eq!(frozen.find_one("fo", Some(1)), Some(("foo".to_string(), 1)));
+        assert_eq!(frozen.find_one("qux", Some(2)), None);
+        assert_eq!(
+            frozen.find_all("bay", 1),
+            vec![("ba", 1), ("bar", 1), ("baz", 1)]
+                .into_iter()
+                .map(|(x, d)| (x.to_string(), d))
+                .collect::<Vec<_>>()
+        );
+        for query in ["", "f", "bax", "fooo", "qux"] {
+            let mut expected = trie.find_all(query, 2);
+            expected.sort_unstable_by_key(|x| (x.1, x.0));
+            let actual = frozen.find_all(query, 2);
+            assert_eq!(actual.len(), expected.len());
+            for ((a, d), (b, e)) in actual.iter().zip(expected) {
+                assert_eq!((a.as_str(), *d), (b, e));
+            }
+        }
+    }
+
+    #[test]
+    fn options() {
+        let mut trie = Trie::with_normalizer(Normalizer::from(Normalization::Lowercase));
+        trie.insert("Foo".to_string());
+        assert_eq!(FrozenTrie::try_from(&trie).err(), Some(BuildError::Options));
+        let mut trie = Trie::with_segmentation(Segmentation::Graphemes);
+        trie.insert("foo".to_string());
+        assert_eq!(FrozenTrie::try_from(&trie).err(), Some(BuildError::Options));
+    }
+
+    #[test]
+    fn bytes() {
+        let frozen = FrozenTrie::from_sorted_iter(["bar", "baz", "foo"]).unwrap();
+        let bytes = frozen.to_bytes();
+        let loaded = FrozenTrie::from_bytes(bytes.as_slice()).unwrap();
+        assert_eq!(loaded.values(), frozen.values());
+        assert_eq!(
+            FrozenTrie::from_bytes(&bytes[..bytes.len() - 1]).err(),
+            Some(DecodeError::Truncated)
+        );
+        // Target of the first edge after the node, not on the path of foo
+        let mut invalid = bytes.clone();
+        invalid[NODES + 12] = 0xff;
+        assert_eq!(
+            FrozenTrie::from_bytes(invalid).err(),
+            Some(DecodeError::Invalid)
+        );
+        // Root beyond the end
+        let mut invalid = bytes.clone();
+        invalid[ROOT..LEN].copy_from_slice(&(bytes.len() as u32).to_le_bytes());
+        assert_eq!(
+            FrozenTrie::from_bytes(invalid).err(),
+            Some(DecodeError::Truncated)
+        );
+        assert_eq!(
+            FrozenTrie::from_bytes(Trie::new().to_bytes()).err(),
+            Some(DecodeError::Header)
+        );
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn open() {
+        let frozen = FrozenTrie::from_sorted_iter(["bar", "baz", "foo"]).unwrap();
+        let path = std::env::temp_dir().join(format!("assrs-{}.bin", std::process::id()));
+        frozen.save(&path).unwrap();
+        let opened = FrozenTrie::open(&path).unwrap();
+        assert_eq!(opened.values(), frozen.values());
+        assert_eq!(opened.find_one("bax", None), Some(("bar".to_string(), 1)));
+        drop(opened);
+
+        std::fs::write(&path, b"invalid").unwrap();
+        let invalid = FrozenTrie::open(&path);
+        assert_eq!(
+            invalid.err().map(|e| e.kind()),
+            Some(io::ErrorKind::InvalidData)
+        );
+
+        // Flipping any byte is an error, or gives a different trie
+        // that can be searched without panicking
+        let bytes = frozen.to_bytes();
+        let mut errors = 0;
+        for i in 0..bytes.len() {
+            let mut flipped = bytes.clone();
+            flipped[i] ^= 0xff;
+            std::fs::write(&path, &flipped).unwrap();
+            match FrozenTrie::open(&path) {
+                Ok(opened) => {
+                    opened.values();
+                    opened.find_all("baz", 3);
+                }
+                Err(e) => {
+                    assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+                    errors += 1;
+                }
+            }
+        }
+        std::fs::remove_file(&path).unwrap();
+        assert!(errors > 0);
+    }
+}
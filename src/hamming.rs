@@ -0,0 +1,33 @@
+//! Hamming distance
+
+/// Find the Hamming distance between two strings
+///
+/// Any difference in length is counted as substitutions of the missing
+/// characters, which keeps it a metric for strings of unequal length
+pub fn hamming(a: &str, b: &str) -> u32 {
+    let mut a = a.chars();
+    let mut b = b.chars();
+    let mut distance = 0;
+    loop {
+        match (a.next(), b.next()) {
+            (Some(x), Some(y)) => distance += (x != y) as u32,
+            (Some(_), None) | (None, Some(_)) => distance += 1,
+            (None, None) => return distance,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distances() {
+        assert_eq!(hamming("", ""), 0);
+        assert_eq!(hamming("foo", "foo"), 0);
+        assert_eq!(hamming("foo", "for"), 1);
+        assert_eq!(hamming("abc", "bca"), 3);
+        assert_eq!(hamming("foo", ""), 3);
+        assert_eq!(hamming("fo", "foo"), 1);
+    }
+}
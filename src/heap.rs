@@ -0,0 +1,95 @@
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+/// Item ordered by its key only, carrying some data along
+#[derive(Debug, Clone, Copy)]
+pub struct Keyed<K, T>(pub K, pub T);
+
+impl<K: PartialEq, T> PartialEq for Keyed<K, T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl<K: Eq, T> Eq for Keyed<K, T> {}
+
+impl<K: PartialOrd, T> PartialOrd for Keyed<K, T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.0.partial_cmp(&other.0)
+    }
+}
+
+impl<K: Ord, T> Ord for Keyed<K, T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.cmp(&other.0)
+    }
+}
+
+/// Max-heap keeping the k smallest items seen
+#[derive(Debug, Clone)]
+pub struct BoundedHeap<T: Ord> {
+    k: usize,
+    heap: BinaryHeap<T>,
+}
+
+impl<T: Ord> BoundedHeap<T> {
+    pub fn new(k: usize) -> Self {
+        Self {
+            k,
+            heap: BinaryHeap::with_capacity(k.saturating_add(1).min(1024)),
+        }
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.heap.len() >= self.k
+    }
+
+    /// Largest item kept, if no more items can be added without replacing it
+    pub fn worst(&self) -> Option<&T> {
+        if self.is_full() {
+            self.heap.peek()
+        } else {
+            None
+        }
+    }
+
+    /// Add item if among the k smallest, returning whether it was kept
+    pub fn push(&mut self, item: T) -> bool {
+        if !self.is_full() {
+            self.heap.push(item);
+            return true;
+        }
+        match self.heap.peek_mut() {
+            Some(mut top) if item < *top => {
+                *top = item;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn into_sorted_vec(self) -> Vec<T> {
+        self.heap.into_sorted_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bounded() {
+        let mut heap = BoundedHeap::new(2);
+        assert_eq!(heap.worst(), None);
+        assert!(heap.push(3));
+        assert!(heap.push(1));
+        assert_eq!(heap.worst(), Some(&3));
+        assert!(heap.push(2));
+        assert!(!heap.push(4));
+        assert_eq!(heap.into_sorted_vec(), vec![1, 2]);
+
+        let mut heap = BoundedHeap::new(0);
+        assert!(!heap.push(1));
+        assert_eq!(heap.into_sorted_vec(), Vec::<i32>::new());
+    }
+}
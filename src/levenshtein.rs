@@ -1,48 +1,141 @@
-use pyo3::prelude::*;
+//! Levenshtein distance and automaton
 
+use std::collections::HashMap;
+
+use crate::heap::{BoundedHeap, Keyed};
+use crate::similarity::MinSimilarity;
+
+/// Automaton for the Levenshtein distance from a fixed string
+///
+/// Uses bitvectors by Myers, in blocks of 64 characters for longer strings.
 #[derive(Debug, Clone)]
 pub struct LevenshteinAutomaton<'a> {
     string: &'a str,
     len: usize,
+    // Mask for the last (or only) block
     mask64: u64,
     chars64: [char; 64],
+    // Match vectors for each block, only used for strings longer than 64
+    peq: HashMap<char, Vec<u64>>,
 }
 
 impl<'a> LevenshteinAutomaton<'a> {
+    /// Create the automaton for string
     pub fn new(string: &'a str) -> Self {
         let len = string.chars().count();
         Self::new_assume_len(string, len)
     }
 
-    fn new_assume_len(string: &'a str, len: usize) -> Self {
+    pub(crate) fn new_assume_len(string: &'a str, len: usize) -> Self {
         let mut chars64 = ['\0'; 64];
         for (i, c) in string.chars().take(64).enumerate() {
             chars64[i] = c;
         }
+        let mut peq = HashMap::new();
+        if len > 64 {
+            let blocks = len.div_ceil(64);
+            for (i, c) in string.chars().enumerate() {
+                peq.entry(c).or_insert_with(|| vec![0u64; blocks])[i / 64] |= 1 << (i % 64);
+            }
+        }
+        let last = len - 64 * len.div_ceil(64).saturating_sub(1);
         Self {
             string,
             len,
-            mask64: 1u64.checked_shl(len as u32).unwrap_or(0).wrapping_sub(1),
+            mask64: 1u64.checked_shl(last as u32).unwrap_or(0).wrapping_sub(1),
             chars64,
+            peq,
         }
     }
 
-    pub fn start(&self) -> LevenshteinState {
+    /// State before reading any characters
+    pub fn start(&self) -> LevenshteinState<'_> {
+        self.start_with(false)
+    }
+
+    /// State before reading any characters, where the string may start
+    /// anywhere in the characters read
+    ///
+    /// The distance is then the lowest from the string to any suffix of the
+    /// characters read, as the first row of the dynamic programming table
+    /// stays at zero.
+    pub fn start_free(&self) -> LevenshteinState<'_> {
+        self.start_with(true)
+    }
+
+    /// State before reading any characters, where the characters read may
+    /// match anywhere in the string
+    ///
+    /// The first column of the dynamic programming table is zero, so that
+    /// [`can_match`](AutomatonState::can_match) is whether the characters
+    /// read are within max_edits of some substring of the string, and the
+    /// distance is the lowest to any suffix of it.
+    pub fn start_anywhere(&self) -> LevenshteinState<'_> {
+        let mut state = self.start();
+        match &mut state {
+            LevenshteinState::Bitvector(s) => s.vp = 0,
+            LevenshteinState::Blocked(s) => s.vp.fill(0),
+            LevenshteinState::General(s) => s.v.fill(0),
+        }
+        state
+    }
+
+    fn start_with(&self, free: bool) -> LevenshteinState<'_> {
         if self.len <= 64 {
             LevenshteinState::Bitvector(LevenshteinBitvector {
                 m: self,
                 vp: self.mask64,
                 vn: 0,
                 offset: 0,
+                free,
             })
         } else {
-            LevenshteinState::General(LevenshteinGeneral {
+            let blocks = self.blocks();
+            let mut vp = vec![u64::MAX; blocks];
+            vp[blocks - 1] = self.mask64;
+            LevenshteinState::Blocked(LevenshteinBlocked {
                 m: self,
-                v: (0..).take(self.len + 1).collect(),
+                vp,
+                vn: vec![0; blocks],
+                offset: 0,
+                free,
             })
         }
     }
 
+    /// Number of 64 character blocks
+    pub(crate) fn blocks(&self) -> usize {
+        self.len.div_ceil(64)
+    }
+
+    /// Positions matching value, for strings up to 64 characters
+    pub(crate) fn match_vector(&self, value: char) -> u64 {
+        let mut pm = 0;
+        let mut x = 1u64;
+        for c in &self.chars64[..self.len] {
+            if c == &value {
+                pm |= x;
+            }
+            x <<= 1;
+        }
+        pm
+    }
+
+    /// Positions matching value in each block, for strings over 64 characters
+    pub(crate) fn match_blocks(&self, value: char) -> Option<&[u64]> {
+        self.peq.get(&value).map(|x| x.as_slice())
+    }
+
+    /// Start state using the full dynamic programming row
+    #[cfg(test)]
+    fn start_general(&self) -> LevenshteinState<'_> {
+        LevenshteinState::General(LevenshteinGeneral {
+            m: self,
+            v: (0..).take(self.len + 1).collect(),
+        })
+    }
+
+    /// Distance from the string of the automaton to other
     pub fn distance(&self, other: &str) -> u32 {
         let mut state = self.start();
         for value in other.chars() {
@@ -50,19 +143,58 @@ impl<'a> LevenshteinAutomaton<'a> {
         }
         state.distance()
     }
+
+    /// Distance from the string of the automaton to other, if at most max_edits
+    pub fn distance_within(&self, other: &str, max_edits: u32) -> Option<u32> {
+        // Every character of difference in length needs an edit
+        if self.len.abs_diff(other.chars().count()) > max_edits as usize {
+            return None;
+        }
+        self.start().distance_within(other, max_edits)
+    }
 }
 
+/// State of a Levenshtein automaton
 #[derive(Debug, Clone)]
 pub enum LevenshteinState<'a> {
+    /// Full dynamic programming row
     General(LevenshteinGeneral<'a>),
+    /// Bitvector for strings up to 64 characters
     Bitvector(LevenshteinBitvector<'a>),
+    /// Bitvector blocks for strings longer than 64 characters
+    Blocked(LevenshteinBlocked<'a>),
 }
 
+/// State of an automaton after reading some characters
+///
+/// The automaton is built for a query string and reads the characters of a
+/// candidate string one at a time.
 pub trait AutomatonState {
+    /// Read a character in place
     fn step_mut(&mut self, value: char);
+    /// Read a character into a new state
     fn step(&self, value: char) -> Self;
+    /// Distance between the query and the characters read so far
     fn distance(&self) -> u32;
+    /// Whether reading more characters can reach a distance of at most
+    /// max_edits
     fn can_match(&self, max_edits: u32) -> bool;
+
+    /// Distance after reading the characters of other, if at most max_edits
+    ///
+    /// Stops reading once the distance can no longer be within max_edits.
+    fn distance_within(mut self, other: &str, max_edits: u32) -> Option<u32>
+    where
+        Self: Sized,
+    {
+        for value in other.chars() {
+            if !self.can_match(max_edits) {
+                return None;
+            }
+            self.step_mut(value);
+        }
+        Some(self.distance()).filter(|&x| x <= max_edits)
+    }
 }
 
 impl AutomatonState for LevenshteinState<'_> {
@@ -70,6 +202,7 @@ impl AutomatonState for LevenshteinState<'_> {
         match self {
             Self::General(s) => s.step_mut(value),
             Self::Bitvector(s) => s.step_mut(value),
+            Self::Blocked(s) => s.step_mut(value),
         }
     }
 
@@ -77,6 +210,7 @@ impl AutomatonState for LevenshteinState<'_> {
         match self {
             Self::General(s) => Self::General(s.step(value)),
             Self::Bitvector(s) => Self::Bitvector(s.step(value)),
+            Self::Blocked(s) => Self::Blocked(s.step(value)),
         }
     }
 
@@ -84,6 +218,7 @@ impl AutomatonState for LevenshteinState<'_> {
         match self {
             Self::General(s) => s.distance(),
             Self::Bitvector(s) => s.distance(),
+            Self::Blocked(s) => s.distance(),
         }
     }
 
@@ -91,10 +226,85 @@ impl AutomatonState for LevenshteinState<'_> {
         match self {
             Self::General(s) => s.can_match(max_edits),
             Self::Bitvector(s) => s.can_match(max_edits),
+            Self::Blocked(s) => s.can_match(max_edits),
         }
     }
 }
 
+/// Automaton, vertical deltas in blocks and offset of a bitvector state
+type Vectors<'s> = (&'s LevenshteinAutomaton<'s>, &'s [u64], &'s [u64], u32);
+
+impl LevenshteinState<'_> {
+    /// Bitvectors of the state, or the row of a general state
+    fn vectors(&self) -> Result<Vectors<'_>, &[u32]> {
+        match self {
+            Self::General(s) => Err(&s.v),
+            Self::Bitvector(s) => Ok((
+                s.m,
+                std::slice::from_ref(&s.vp),
+                std::slice::from_ref(&s.vn),
+                s.offset,
+            )),
+            Self::Blocked(s) => Ok((s.m, s.vp.as_slice(), s.vn.as_slice(), s.offset)),
+        }
+    }
+
+    /// Distances from each prefix of the string of the automaton, shortest first
+    pub(crate) fn distances(&self) -> Vec<u32> {
+        let (m, vp, vn, offset) = match self.vectors() {
+            Ok(vectors) => vectors,
+            Err(row) => return row.to_vec(),
+        };
+        let mut score = offset;
+        let mut result = Vec::with_capacity(m.len + 1);
+        result.push(score);
+        for i in 0..m.len {
+            score += (vp[i / 64] >> (i % 64) & 1) as u32;
+            score -= (vn[i / 64] >> (i % 64) & 1) as u32;
+            result.push(score);
+        }
+        result
+    }
+
+    /// Lengths of the prefixes of the string of the automaton within
+    /// max_edits, shortest first
+    ///
+    /// Skips blocks of 64 characters where none can be within max_edits, so
+    /// that few prefixes are found quickly in a long string.
+    pub(crate) fn prefixes_within(&self, max_edits: u32) -> Vec<usize> {
+        let (m, vp, vn, offset) = match self.vectors() {
+            Ok(vectors) => vectors,
+            Err(row) => return (0..row.len()).filter(|&i| row[i] <= max_edits).collect(),
+        };
+        let mut result = Vec::new();
+        let mut score = offset;
+        if score <= max_edits {
+            result.push(0);
+        }
+        for i in 0..vp.len() {
+            let mask = if i + 1 == vp.len() {
+                m.mask64
+            } else {
+                u64::MAX
+            };
+            let (vp, vn) = (vp[i] & mask, vn[i] & mask);
+            if score > max_edits && score - max_descent(vp, vn) > max_edits {
+                score = score + vp.count_ones() - vn.count_ones();
+                continue;
+            }
+            for j in 0..64.min(m.len - 64 * i) {
+                score += (vp >> j & 1) as u32;
+                score -= (vn >> j & 1) as u32;
+                if score <= max_edits {
+                    result.push(64 * i + j + 1);
+                }
+            }
+        }
+        result
+    }
+}
+
+/// Dynamic programming row of a Levenshtein automaton
 #[derive(Debug, Clone)]
 pub struct LevenshteinGeneral<'a> {
     m: &'a LevenshteinAutomaton<'a>,
@@ -131,26 +341,22 @@ impl AutomatonState for LevenshteinGeneral<'_> {
     }
 }
 
+/// Single bitvector state of a Levenshtein automaton
 #[derive(Debug, Clone, Copy)]
 pub struct LevenshteinBitvector<'a> {
-    m: &'a LevenshteinAutomaton<'a>,
-    vp: u64,
-    vn: u64,
-    offset: u32,
+    pub(crate) m: &'a LevenshteinAutomaton<'a>,
+    pub(crate) vp: u64,
+    pub(crate) vn: u64,
+    pub(crate) offset: u32,
+    // Whether the first row stays at zero rather than increasing
+    pub(crate) free: bool,
 }
 
 impl AutomatonState for LevenshteinBitvector<'_> {
     fn step_mut(&mut self, value: char) {
         // Myers as described by Hyyro
         // Step 1: D0
-        let mut pm = 0;
-        let mut x = 1u64;
-        for c in &self.m.chars64[..self.m.len] {
-            if c == &value {
-                pm |= x;
-            }
-            x <<= 1;
-        }
+        let pm = self.m.match_vector(value);
         let d0 = (((pm & self.vp).wrapping_add(self.vp)) ^ self.vp) | pm | self.vn;
         // Step 2-3: HP and HN
         let mut hp = self.vn | !(d0 | self.vp);
@@ -163,12 +369,12 @@ impl AutomatonState for LevenshteinBitvector<'_> {
         //     score -= 1;
         // }
         // Step 6-7: VP and VN
-        hp = (hp << 1) | 1;
+        hp = (hp << 1) | !self.free as u64;
         hn <<= 1;
 
         self.vp = hn | !(d0 | hp);
         self.vn = hp & d0;
-        self.offset += 1;
+        self.offset += !self.free as u32;
     }
 
     fn step(&self, value: char) -> Self {
@@ -183,54 +389,239 @@ impl AutomatonState for LevenshteinBitvector<'_> {
     }
 
     fn can_match(&self, max_edits: u32) -> bool {
-        self.offset <= max_edits || {
-            let mut vpi = self.vp & self.m.mask64;
-            let mut nvni = !(self.vn & self.m.mask64);
-            while vpi != 0 && !nvni != 0 {
-                // The minimum is preserved in this operation
-                // Earlier positive steps cancel out later negative ones
-                let x = nvni.wrapping_add(vpi);
-                vpi &= x;
-                nvni |= x;
+        self.offset <= max_edits
+            || self.offset - max_descent(self.vp & self.m.mask64, self.vn & self.m.mask64)
+                <= max_edits
+    }
+}
+
+/// Multiple bitvector blocks state of a Levenshtein automaton
+#[derive(Debug, Clone)]
+pub struct LevenshteinBlocked<'a> {
+    pub(crate) m: &'a LevenshteinAutomaton<'a>,
+    pub(crate) vp: Vec<u64>,
+    pub(crate) vn: Vec<u64>,
+    pub(crate) offset: u32,
+    // Whether the first row stays at zero rather than increasing
+    pub(crate) free: bool,
+}
+
+impl LevenshteinBlocked<'_> {
+    fn block(&self, i: usize) -> (u64, u64) {
+        if i + 1 == self.vp.len() {
+            (self.vp[i] & self.m.mask64, self.vn[i] & self.m.mask64)
+        } else {
+            (self.vp[i], self.vn[i])
+        }
+    }
+}
+
+impl AutomatonState for LevenshteinBlocked<'_> {
+    fn step_mut(&mut self, value: char) {
+        // Myers with the horizontal delta carried between blocks (Hyyro)
+        let pms = self.m.match_blocks(value);
+        // The first row increases by one in each step, unless the start is free
+        let mut hin = !self.free as i32;
+        for i in 0..self.vp.len() {
+            let mut pm = pms.map_or(0, |x| x[i]);
+            let vp = self.vp[i];
+            let vn = self.vn[i];
+            let xv = pm | vn;
+            if hin < 0 {
+                pm |= 1;
+            }
+            let xh = (((pm & vp).wrapping_add(vp)) ^ vp) | pm;
+            let mut hp = vn | !(xh | vp);
+            let mut hn = xh & vp;
+            let hout = (hp >> 63) as i32 - (hn >> 63) as i32;
+            hp <<= 1;
+            hn <<= 1;
+            if hin > 0 {
+                hp |= 1;
+            } else if hin < 0 {
+                hn |= 1;
+            }
+            self.vp[i] = hn | !(xv | hp);
+            self.vn[i] = hp & xv;
+            hin = hout;
+        }
+        self.offset += !self.free as u32;
+    }
+
+    fn step(&self, value: char) -> Self {
+        let mut new = self.clone();
+        new.step_mut(value);
+        new
+    }
+
+    fn distance(&self) -> u32 {
+        (0..self.vp.len()).fold(self.offset, |score, i| {
+            let (vp, vn) = self.block(i);
+            score + vp.count_ones() - vn.count_ones()
+        })
+    }
+
+    fn can_match(&self, max_edits: u32) -> bool {
+        let mut score = self.offset;
+        for i in 0..self.vp.len() {
+            let (vp, vn) = self.block(i);
+            if score <= max_edits || score - max_descent(vp, vn) <= max_edits {
+                return true;
             }
-            self.offset - nvni.count_zeros()
-        } <= max_edits
+            score = score + vp.count_ones() - vn.count_ones();
+        }
+        score <= max_edits
     }
 }
 
+/// Largest decrease of the running score over the vertical deltas of a block
+fn max_descent(vp: u64, vn: u64) -> u32 {
+    let mut vpi = vp;
+    let mut nvni = !vn;
+    while vpi != 0 && !nvni != 0 {
+        // The minimum is preserved in this operation
+        // Earlier positive steps cancel out later negative ones
+        let x = nvni.wrapping_add(vpi);
+        vpi &= x;
+        nvni |= x;
+    }
+    nvni.count_zeros()
+}
+
 /// Find the Levenshtein distance between two strings
-#[pyfunction]
 pub fn levenshtein(a: &str, b: &str) -> u32 {
     if a == b {
         return 0;
     }
+    let (a, len_a, b) = pattern_first(a, b);
+    let automaton = LevenshteinAutomaton::new_assume_len(a, len_a);
+    automaton.distance(b)
+}
+
+/// Find the Levenshtein distance between two strings, if at most max_edits
+///
+/// Faster than finding the distance when it exceeds max_edits.
+pub fn levenshtein_within(a: &str, b: &str, max_edits: u32) -> Option<u32> {
+    if a == b {
+        return Some(0);
+    }
+    let (a, len_a, b) = pattern_first(a, b);
+    let automaton = LevenshteinAutomaton::new_assume_len(a, len_a);
+    automaton.distance_within(b, max_edits)
+}
+
+/// Order a pair of strings to use the better one as the pattern
+///
+/// Prefers a single block, otherwise the fewest blocks
+pub(crate) fn pattern_first<'a>(a: &'a str, b: &'a str) -> (&'a str, usize, &'a str) {
     let len_a = a.chars().count();
     let len_b = b.chars().count();
-
-    let (a, len_a, b) = if (len_a < len_b || len_a > 64) && len_b <= 64 {
+    if (len_a < len_b || len_a > 64) && (len_b <= 64 || len_b < len_a) {
         (b, len_b, a)
     } else {
         (a, len_a, b)
-    };
-    let automaton = LevenshteinAutomaton::new_assume_len(a, len_a);
-    automaton.distance(b)
+    }
 }
 
-/// Find the best match in a list of choices
+/// Find the best match in choices
 ///
 /// Returns (choice, distance, index) or None (for empty choices)
-#[pyfunction]
-pub fn levenshtein_extract(query: &str, choices: Vec<&str>) -> Option<(String, u32, usize)> {
-    let mut best = None;
+pub fn levenshtein_extract<S: AsRef<str>>(
+    query: &str,
+    choices: impl IntoIterator<Item = S>,
+) -> Option<(String, u32, usize)> {
+    let mut best: Option<(u32, usize, String)> = None;
     let automaton = LevenshteinAutomaton::new(query);
-    for (i, x) in choices.iter().enumerate() {
-        let distance = automaton.distance(x);
-        best = Some(best.unwrap_or((distance, i, x)).min((distance, i, x)));
-        if distance == 0 {
-            break;
+    for (i, x) in choices.into_iter().enumerate() {
+        // Only strictly better choices replace the best so far
+        let max_edits = best.as_ref().map_or(u32::MAX, |x| x.0 - 1);
+        let x = x.as_ref();
+        if let Some(distance) = automaton.distance_within(x, max_edits) {
+            best = Some((distance, i, x.to_string()));
+            if distance == 0 {
+                break;
+            }
         }
     }
-    best.map(|x| (x.2.to_string(), x.0, x.1))
+    best.map(|x| (x.2, x.0, x.1))
+}
+
+/// Find the best match in choices with at least min_similarity
+///
+/// Returns (choice, distance, index) or None (for no similar choices)
+pub fn levenshtein_extract_similar<S: AsRef<str>>(
+    query: &str,
+    choices: impl IntoIterator<Item = S>,
+    min_similarity: f64,
+) -> Option<(String, u32, usize)> {
+    let bound = MinSimilarity::new(query, min_similarity);
+    let mut best: Option<(u32, usize, String)> = None;
+    let automaton = LevenshteinAutomaton::new(query);
+    for (i, x) in choices.into_iter().enumerate() {
+        let x = x.as_ref();
+        let Some(max_edits) = bound.max_edits(x.chars().count()) else {
+            continue;
+        };
+        let max_edits = best.as_ref().map_or(max_edits, |x| max_edits.min(x.0 - 1));
+        if let Some(distance) = automaton.distance_within(x, max_edits) {
+            best = Some((distance, i, x.to_string()));
+            if distance == 0 {
+                break;
+            }
+        }
+    }
+    best.map(|x| (x.2, x.0, x.1))
+}
+
+/// Find the k best matches in choices
+///
+/// Returns (choice, distance, index) sorted by distance and then index
+pub fn levenshtein_extract_k<S: AsRef<str>>(
+    query: &str,
+    choices: impl IntoIterator<Item = S>,
+    k: usize,
+) -> Vec<(String, u32, usize)> {
+    let mut heap = BoundedHeap::new(k);
+    let automaton = LevenshteinAutomaton::new(query);
+    for (i, x) in choices.into_iter().enumerate() {
+        // Ties with the worst kept lose on index
+        let max_edits = match heap.worst() {
+            Some(&Keyed((0, _), _)) => break,
+            Some(&Keyed((distance, _), _)) => distance - 1,
+            None if k == 0 => break,
+            None => u32::MAX,
+        };
+        let x = x.as_ref();
+        if let Some(distance) = automaton.distance_within(x, max_edits) {
+            heap.push(Keyed((distance, i), x.to_string()));
+        }
+    }
+    heap.into_sorted_vec()
+        .into_iter()
+        .map(|Keyed((distance, i), x)| (x, distance, i))
+        .collect()
+}
+
+/// Find all matches in choices within max_edits of query
+///
+/// Returns (choice, distance, index) sorted by distance and then index
+pub fn levenshtein_extract_all<S: AsRef<str>>(
+    query: &str,
+    choices: impl IntoIterator<Item = S>,
+    max_edits: u32,
+) -> Vec<(String, u32, usize)> {
+    let automaton = LevenshteinAutomaton::new(query);
+    let mut result: Vec<_> = choices
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, x)| {
+            let x = x.as_ref();
+            let distance = automaton.distance_within(x, max_edits)?;
+            Some((x.to_string(), distance, i))
+        })
+        .collect();
+    result.sort_by_key(|x| (x.1, x.2));
+    result
 }
 
 #[cfg(test)]
@@ -238,6 +629,7 @@ mod tests {
     use super::*;
 
     #[test]
+    #[allow(clippy::needless_borrow)]
     fn distances() {
         assert_eq!(levenshtein("foo", "bar"), 3);
         assert_eq!(levenshtein("foo", ""), 3);
@@ -254,9 +646,39 @@ mod tests {
         assert_eq!(levenshtein(&"abcd".repeat(64), &"abcd".repeat(128)), 256);
     }
 
+    #[test]
+    fn within() {
+        assert_eq!(levenshtein_within("kitten", "sitting", 3), Some(3));
+        assert_eq!(levenshtein_within("kitten", "sitting", 2), None);
+        assert_eq!(levenshtein_within("foo", "foo", 0), Some(0));
+        assert_eq!(levenshtein_within("", "bar", 2), None);
+        let long = "abcde".repeat(20);
+        let strings = [
+            "",
+            "abc",
+            "kitten",
+            "sitting",
+            &long,
+            &long[3..],
+            &long[..64],
+            &long[1..65],
+        ];
+        for a in strings {
+            for b in strings {
+                let distance = levenshtein(a, b);
+                for max_edits in [0, 1, 2, 3, 10, 50, 100, u32::MAX] {
+                    let expected = Some(distance).filter(|&x| x <= max_edits);
+                    assert_eq!(levenshtein_within(a, b, max_edits), expected);
+                    let automaton = LevenshteinAutomaton::new(a);
+                    assert_eq!(automaton.start().distance_within(b, max_edits), expected);
+                }
+            }
+        }
+    }
+
     #[test]
     fn extract() {
-        assert_eq!(levenshtein_extract("foo", vec![]), None);
+        assert_eq!(levenshtein_extract("foo", Vec::<&str>::new()), None);
         assert_eq!(
             levenshtein_extract("bar", vec!["bar"]),
             Some((String::from("bar"), 0, 0))
@@ -265,6 +687,67 @@ mod tests {
             levenshtein_extract("baz", vec!["foo", "bar"]),
             Some((String::from("bar"), 1, 1))
         );
+        assert_eq!(
+            levenshtein_extract("baz", vec!["qux", "bay", "foo", "bar", "baz"]),
+            Some((String::from("baz"), 0, 4))
+        );
+        assert_eq!(
+            levenshtein_extract("baz", vec!["qux", "bay", "foo", "bar"]),
+            Some((String::from("bay"), 1, 1))
+        );
+        let choices = [String::from("qux"), String::from("bar")];
+        assert_eq!(
+            levenshtein_extract("baz", &choices),
+            Some((String::from("bar"), 1, 1))
+        );
+        assert_eq!(
+            levenshtein_extract_similar("baz", vec!["foo", "bar"], 0.6),
+            Some((String::from("bar"), 1, 1))
+        );
+        assert_eq!(
+            levenshtein_extract_similar("baz", vec!["foo", "bar"], 0.7),
+            None
+        );
+        assert_eq!(
+            levenshtein_extract_similar("abcdefx", vec!["ab", "abcdefgh"], 0.75),
+            Some((String::from("abcdefgh"), 2, 1))
+        );
+    }
+
+    #[test]
+    fn extract_k() {
+        let choices = ["foo", "bar", "baz", "ba", "bar", "qux"];
+        assert_eq!(levenshtein_extract_k("baz", choices, 0), vec![]);
+        assert_eq!(
+            levenshtein_extract_k("baz", choices, 3),
+            vec![
+                (String::from("baz"), 0, 2),
+                (String::from("bar"), 1, 1),
+                (String::from("ba"), 1, 3)
+            ]
+        );
+        assert_eq!(levenshtein_extract_k("baz", choices, 10).len(), 6);
+        assert_eq!(
+            levenshtein_extract_k("bar", choices.map(String::from), 2),
+            vec![(String::from("bar"), 0, 1), (String::from("bar"), 0, 4)]
+        );
+        assert_eq!(levenshtein_extract_k("foo", Vec::<&str>::new(), 2), vec![]);
+    }
+
+    #[test]
+    fn extract_all() {
+        let choices = ["foo", "bar", "baz", "ba", "bar", "qux"];
+        assert_eq!(levenshtein_extract_all("baz", choices, 0).len(), 1);
+        assert_eq!(
+            levenshtein_extract_all("baz", choices.iter(), 1),
+            vec![
+                (String::from("baz"), 0, 2),
+                (String::from("bar"), 1, 1),
+                (String::from("ba"), 1, 3),
+                (String::from("bar"), 1, 4)
+            ]
+        );
+        assert_eq!(levenshtein_extract_all("baz", choices, 3).len(), 6);
     }
 
     #[test]
@@ -325,4 +808,111 @@ mod tests {
         assert!(state.can_match(96));
         assert!(state.can_match(u32::MAX));
     }
+
+    #[test]
+    fn free_start() {
+        let automaton = LevenshteinAutomaton::new("acme");
+        let mut state = automaton.start_free();
+        for c in "contact acne".chars() {
+            state.step_mut(c);
+        }
+        assert_eq!(state.distance(), 1);
+        assert!(state.can_match(0));
+
+        let long = "abcd".repeat(20);
+        let automaton = LevenshteinAutomaton::new(&long);
+        let text = "xyz".to_string() + &long;
+        let state = text.chars().fold(automaton.start_free(), |x, c| x.step(c));
+        assert!(matches!(state, LevenshteinState::Blocked(_)));
+        assert_eq!(state.distance(), 0);
+        assert_eq!(state.distances()[..2], [0, 1]);
+    }
+
+    #[test]
+    fn start_anywhere() {
+        let automaton = LevenshteinAutomaton::new("contact acne");
+        let state = "acme"
+            .chars()
+            .fold(automaton.start_anywhere(), |x, c| x.step(c));
+        assert!(!state.can_match(0));
+        assert!(state.can_match(1));
+        assert_eq!(state.distance(), 1);
+
+        // Lowest distances to any substring and any suffix of the string
+        for string in ["contact acne", &("abcd".repeat(20) + "xyz")] {
+            let automaton = LevenshteinAutomaton::new(string);
+            for query in ["", "acme", "dabx", "cont", "xyzz", "tact"] {
+                let state = query
+                    .chars()
+                    .fold(automaton.start_anywhere(), |x, c| x.step(c));
+                let offsets = || string.char_indices().map(|x| x.0).chain([string.len()]);
+                let substring = offsets()
+                    .flat_map(|i| offsets().filter(move |&j| j >= i).map(move |j| (i, j)))
+                    .map(|(i, j)| levenshtein(query, &string[i..j]))
+                    .min()
+                    .unwrap();
+                let suffix = offsets()
+                    .map(|i| levenshtein(query, &string[i..]))
+                    .min()
+                    .unwrap();
+                assert!(state.can_match(substring));
+                assert!(substring == 0 || !state.can_match(substring - 1));
+                assert_eq!(state.distance(), suffix, "{string} {query}");
+            }
+        }
+    }
+
+    #[test]
+    fn prefixes_within() {
+        let string = "abcd".repeat(40) + "xyz";
+        let automaton = LevenshteinAutomaton::new(&string);
+        for query in ["", "dab", "bcdx", "xyz", "abcdabcd", "qqqq"] {
+            let state = query
+                .chars()
+                .fold(automaton.start_anywhere(), |x, c| x.step(c));
+            for max_edits in 0..4 {
+                let distances = state.distances();
+                let expected: Vec<_> = (0..distances.len())
+                    .filter(|&i| distances[i] <= max_edits)
+                    .collect();
+                assert_eq!(state.prefixes_within(max_edits), expected);
+            }
+        }
+        let automaton = LevenshteinAutomaton::new("kitten");
+        let state = "sit".chars().fold(automaton.start(), |x, c| x.step(c));
+        assert_eq!(state.prefixes_within(1), vec![3]);
+        assert_eq!(state.prefixes_within(2), vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn blocked_automaton() {
+        // Deterministic pseudo-random strings over a small alphabet
+        let mut seed = 12345u64;
+        let mut random_string = |len: usize| {
+            (0..len)
+                .map(|_| {
+                    seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+                    (b'a' + (seed >> 60) as u8 % 4) as char
+                })
+                .collect::<String>()
+        };
+        for (len_a, len_b) in [(65, 10), (100, 100), (128, 70), (200, 300), (129, 0)] {
+            let a = random_string(len_a);
+            let b = random_string(len_b);
+            let automaton = LevenshteinAutomaton::new(&a);
+            let mut blocked = automaton.start();
+            let mut general = automaton.start_general();
+            assert!(matches!(blocked, LevenshteinState::Blocked(_)));
+            for c in b.chars() {
+                blocked.step_mut(c);
+                general.step_mut(c);
+                assert_eq!(blocked.distance(), general.distance());
+                for max_edits in 0..=len_a as u32 {
+                    assert_eq!(blocked.can_match(max_edits), general.can_match(max_edits));
+                }
+            }
+            assert_eq!(levenshtein(&a, &b), general.distance());
+            assert_eq!(levenshtein(&b, &a), general.distance());
+        }
+    }
 }
@@ -1,18 +1,99 @@
+//! Approximate string search
+//!
+//! Query an index of strings for the closest match, using a [`Trie`]
+//! traversed with a Levenshtein automaton or a [`BKTree`] for any [`Metric`].
+//!
+//! ```
+//! use assrs::{levenshtein, BKTree, Trie};
+//!
+//! let trie = Trie::from_iter(["foo", "bar"].map(String::from));
+//! assert_eq!(trie.find_one("baz", None), Some(("bar", 1)));
+//! assert_eq!(trie.find_one("abc", Some(1)), None);
+//!
+//! let tree = BKTree::from_iter(["foo", "bar"].map(String::from));
+//! assert_eq!(tree.find_one("baz", None), Some(("bar", 1)));
+//!
+//! assert_eq!(levenshtein("kitten", "sitting"), 3);
+//! ```
+//!
+//! Python bindings are built with the `python` feature.
+#![warn(missing_docs)]
+
+#[cfg(feature = "python")]
 use pyo3::prelude::*;
 
-use crate::bktree::BKTree;
-use crate::trie::Trie;
+pub mod bktree;
+pub mod editops;
+pub mod frozen;
+pub mod hamming;
+mod heap;
+pub mod levenshtein;
+pub mod metric;
+pub mod normalize;
+pub mod osa;
+pub mod partial;
+#[cfg(feature = "python")]
+// Macros from this pyo3 version trip the lint on newer compilers
+#[allow(non_local_definitions)]
+mod python;
+pub mod segmentation;
+pub mod serialize;
+pub mod similarity;
+pub mod trie;
+pub mod weighted;
 
-mod bktree;
-mod levenshtein;
-mod trie;
+pub use crate::bktree::{BKTree, BKTreeMap};
+pub use crate::editops::{editops, opcodes, EditKind, Editop, Opcode};
+pub use crate::frozen::FrozenTrie;
+pub use crate::hamming::hamming;
+pub use crate::levenshtein::{
+    levenshtein, levenshtein_extract, levenshtein_extract_all, levenshtein_extract_k,
+    levenshtein_extract_similar, levenshtein_within, AutomatonState, LevenshteinAutomaton,
+};
+pub use crate::metric::{BuiltinMetric, Hamming, Levenshtein, Metric, Osa};
+pub use crate::normalize::{Normalization, Normalizer};
+pub use crate::osa::{osa, OsaAutomaton};
+pub use crate::partial::{fuzzy_find, partial_ratio};
+pub use crate::segmentation::{
+    levenshtein_graphemes, Graphemes, Segmentation, Segmented, TooManyClusters,
+};
+pub use crate::serialize::DecodeError;
+pub use crate::similarity::{levenshtein_similarity, osa_similarity, MinSimilarity};
+pub use crate::trie::{Selection, Trie, TrieMap};
+pub use crate::weighted::{
+    substitution_levenshtein, weighted_levenshtein, Substitutions, WeightedAutomaton, Weights,
+};
 
 /// Approximate string searching
+#[cfg(feature = "python")]
 #[pymodule]
 fn assrs(_py: Python, m: &PyModule) -> PyResult<()> {
-    m.add_function(wrap_pyfunction!(levenshtein::levenshtein, m)?)?;
-    m.add_function(wrap_pyfunction!(levenshtein::levenshtein_extract, m)?)?;
-    m.add_class::<BKTree>()?;
-    m.add_class::<Trie>()?;
+    use crate::python::bktree::{PyBKTree, PyBKTreeMap};
+    use crate::python::frozen::PyFrozenTrie;
+    use crate::python::functions;
+    use crate::python::trie::{PyTrie, PyTrieMap};
+    use crate::python::weighted::PySubstitutions;
+
+    m.add_function(wrap_pyfunction!(functions::levenshtein, m)?)?;
+    m.add_function(wrap_pyfunction!(functions::levenshtein_similarity, m)?)?;
+    m.add_function(wrap_pyfunction!(functions::levenshtein_extract, m)?)?;
+    m.add_function(wrap_pyfunction!(functions::levenshtein_extract_k, m)?)?;
+    m.add_function(wrap_pyfunction!(functions::levenshtein_extract_all, m)?)?;
+    m.add_function(wrap_pyfunction!(functions::levenshtein_extract_batch, m)?)?;
+    m.add_function(wrap_pyfunction!(functions::cdist, m)?)?;
+    m.add_function(wrap_pyfunction!(functions::fuzzy_find, m)?)?;
+    m.add_function(wrap_pyfunction!(functions::partial_ratio, m)?)?;
+    m.add_function(wrap_pyfunction!(functions::editops, m)?)?;
+    m.add_function(wrap_pyfunction!(functions::opcodes, m)?)?;
+    m.add_function(wrap_pyfunction!(functions::osa, m)?)?;
+    m.add_function(wrap_pyfunction!(functions::damerau_levenshtein, m)?)?;
+    m.add_function(wrap_pyfunction!(functions::osa_similarity, m)?)?;
+    m.add_function(wrap_pyfunction!(functions::hamming, m)?)?;
+    m.add_class::<PyBKTree>()?;
+    m.add_class::<PyBKTreeMap>()?;
+    m.add_class::<PyFrozenTrie>()?;
+    m.add_class::<PyTrie>()?;
+    m.add_class::<PyTrieMap>()?;
+    m.add_class::<PySubstitutions>()?;
     Ok(())
 }
@@ -0,0 +1,135 @@
+//! Metrics for indexing strings in a BK-tree
+
+use crate::hamming::hamming;
+use crate::levenshtein::{levenshtein, LevenshteinAutomaton};
+use crate::osa::{osa, OsaAutomaton};
+use crate::segmentation::Segmentation;
+use crate::serialize::DecodeError;
+
+/// Distance between a pair of strings
+///
+/// Searching a BK-tree is only exact for a metric satisfying the triangle
+/// inequality.
+pub trait Metric {
+    /// Distance between a and b
+    fn distance(&self, a: &str, b: &str) -> u32;
+
+    /// Distance from query to other strings, for comparing many against it
+    fn query<'a>(&'a self, query: &'a str) -> Box<dyn Fn(&str) -> u32 + 'a> {
+        Box::new(move |other| self.distance(query, other))
+    }
+
+    /// Length of s in the units edits are counted in
+    fn len(&self, s: &str) -> usize {
+        s.chars().count()
+    }
+
+    /// Whether a distance could not be computed, such that the distances
+    /// returned since are meaningless until the caller handles the failure
+    ///
+    /// BK-trees are left unchanged by inserts and removes while it holds.
+    fn failed(&self) -> bool {
+        false
+    }
+
+    /// Which metric built into the crate this is, if any
+    ///
+    /// Serialized BK-trees store it, so that they are only loaded with the
+    /// metric their distances were computed with.
+    fn builtin(&self) -> Option<BuiltinMetric> {
+        None
+    }
+
+    /// Units that edits are counted in
+    fn segmentation(&self) -> Segmentation {
+        Segmentation::Codepoints
+    }
+}
+
+/// Metrics built into the crate
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BuiltinMetric {
+    /// [`Levenshtein`]
+    Levenshtein,
+    /// [`Osa`]
+    Osa,
+    /// [`Hamming`]
+    Hamming,
+}
+
+impl BuiltinMetric {
+    /// Code of the metric in serialized bytes, zero for other metrics
+    pub(crate) fn code(metric: Option<Self>) -> u8 {
+        match metric {
+            None => 0,
+            Some(Self::Levenshtein) => 1,
+            Some(Self::Osa) => 2,
+            Some(Self::Hamming) => 3,
+        }
+    }
+
+    pub(crate) fn from_code(code: u8) -> Result<Option<Self>, DecodeError> {
+        match code {
+            0 => Ok(None),
+            1 => Ok(Some(Self::Levenshtein)),
+            2 => Ok(Some(Self::Osa)),
+            3 => Ok(Some(Self::Hamming)),
+            _ => Err(DecodeError::Invalid),
+        }
+    }
+}
+
+/// Levenshtein distance
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Levenshtein;
+
+impl Metric for Levenshtein {
+    fn distance(&self, a: &str, b: &str) -> u32 {
+        levenshtein(a, b)
+    }
+
+    fn query<'a>(&'a self, query: &'a str) -> Box<dyn Fn(&str) -> u32 + 'a> {
+        let automaton = LevenshteinAutomaton::new(query);
+        Box::new(move |other| automaton.distance(other))
+    }
+
+    fn builtin(&self) -> Option<BuiltinMetric> {
+        Some(BuiltinMetric::Levenshtein)
+    }
+}
+
+/// Optimal string alignment (restricted Damerau-Levenshtein) distance
+///
+/// Does not satisfy the triangle inequality, so BK-tree searches may miss
+/// some matches.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Osa;
+
+impl Metric for Osa {
+    fn distance(&self, a: &str, b: &str) -> u32 {
+        osa(a, b)
+    }
+
+    fn query<'a>(&'a self, query: &'a str) -> Box<dyn Fn(&str) -> u32 + 'a> {
+        let automaton = OsaAutomaton::new(query);
+        Box::new(move |other| automaton.distance(other))
+    }
+
+    fn builtin(&self) -> Option<BuiltinMetric> {
+        Some(BuiltinMetric::Osa)
+    }
+}
+
+/// Hamming distance, counting differences in length as substitutions
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Hamming;
+
+impl Metric for Hamming {
+    fn distance(&self, a: &str, b: &str) -> u32 {
+        hamming(a, b)
+    }
+
+    fn builtin(&self) -> Option<BuiltinMetric> {
+        Some(BuiltinMetric::Hamming)
+    }
+}
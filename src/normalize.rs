@@ -0,0 +1,140 @@
+//! Normalization of strings before indexing and searching
+//!
+//! Indexes can normalize keys when inserting and queries when searching, so
+//! that for example case or accents do not count as edits, while still
+//! returning the keys as they were inserted.
+
+use std::borrow::Cow;
+
+use unicode_normalization::char::is_combining_mark;
+use unicode_normalization::{is_nfc, is_nfkc, UnicodeNormalization};
+
+/// Single normalization step
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Normalization {
+    /// Lowercase with the Unicode lowercase mapping
+    Lowercase,
+    /// Unicode default case folding, which also folds e.g. ß into ss
+    Casefold,
+    /// Canonical composition, Normalization Form C
+    Nfc,
+    /// Compatibility composition, Normalization Form KC
+    Nfkc,
+    /// Remove combining marks after canonical decomposition
+    StripAccents,
+}
+
+impl Normalization {
+    // Indexed by the serialized code of each step
+    pub(crate) const ALL: [Self; 5] = [
+        Self::Lowercase,
+        Self::Casefold,
+        Self::Nfc,
+        Self::Nfkc,
+        Self::StripAccents,
+    ];
+
+    /// Apply the step to s
+    pub fn apply<'a>(&self, s: &'a str) -> Cow<'a, str> {
+        match self {
+            Self::Lowercase if !s.chars().any(char::is_uppercase) => Cow::Borrowed(s),
+            Self::Lowercase => Cow::Owned(s.to_lowercase()),
+            Self::Casefold => Cow::Owned(caseless::default_case_fold_str(s)),
+            Self::Nfc if is_nfc(s) => Cow::Borrowed(s),
+            Self::Nfc => Cow::Owned(s.nfc().collect()),
+            Self::Nfkc if is_nfkc(s) => Cow::Borrowed(s),
+            Self::Nfkc => Cow::Owned(s.nfkc().collect()),
+            Self::StripAccents if s.is_ascii() => Cow::Borrowed(s),
+            Self::StripAccents => {
+                Cow::Owned(s.nfd().filter(|&c| !is_combining_mark(c)).nfc().collect())
+            }
+        }
+    }
+}
+
+/// Normalization steps applied in order, none by default
+#[derive(Debug, Default, Clone, PartialEq, Eq, Hash)]
+pub struct Normalizer {
+    steps: Vec<Normalization>,
+}
+
+impl Normalizer {
+    /// Create a normalizer applying steps in order
+    pub fn new(steps: impl IntoIterator<Item = Normalization>) -> Self {
+        steps.into_iter().collect()
+    }
+
+    /// Steps applied in order
+    pub fn steps(&self) -> &[Normalization] {
+        &self.steps
+    }
+
+    /// Whether the normalizer leaves strings unchanged
+    pub fn is_none(&self) -> bool {
+        self.steps.is_empty()
+    }
+
+    /// Normalize s
+    pub fn normalize<'a>(&self, s: &'a str) -> Cow<'a, str> {
+        let mut result = Cow::Borrowed(s);
+        for step in &self.steps {
+            if let Cow::Owned(x) = step.apply(&result) {
+                result = Cow::Owned(x);
+            }
+        }
+        result
+    }
+}
+
+impl From<Normalization> for Normalizer {
+    fn from(step: Normalization) -> Self {
+        Self { steps: vec![step] }
+    }
+}
+
+impl FromIterator<Normalization> for Normalizer {
+    fn from_iter<I: IntoIterator<Item = Normalization>>(iter: I) -> Self {
+        Self {
+            steps: iter.into_iter().collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps() {
+        assert_eq!(Normalization::Lowercase.apply("FOO Bar"), "foo bar");
+        assert!(matches!(
+            Normalization::Lowercase.apply("foo"),
+            Cow::Borrowed(_)
+        ));
+        assert_eq!(Normalization::Lowercase.apply("Straße"), "straße");
+        assert_eq!(Normalization::Casefold.apply("Straße"), "strasse");
+        assert_eq!(Normalization::Nfc.apply("cafe\u{301}"), "café");
+        assert_eq!(Normalization::Nfc.apply("ﬁ"), "ﬁ");
+        assert_eq!(Normalization::Nfkc.apply("ﬁ"), "fi");
+        assert_eq!(Normalization::Nfkc.apply("Ⅻ"), "XII");
+        assert_eq!(Normalization::StripAccents.apply("café"), "cafe");
+        assert_eq!(Normalization::StripAccents.apply("cafe\u{301}"), "cafe");
+        assert_eq!(Normalization::StripAccents.apply("Ångström"), "Angstrom");
+        // Hangul syllables are recomposed rather than left as jamo
+        assert_eq!(Normalization::StripAccents.apply("한글"), "한글");
+    }
+
+    #[test]
+    fn normalizer() {
+        let normalizer = Normalizer::default();
+        assert!(normalizer.is_none());
+        assert!(matches!(normalizer.normalize("Foo"), Cow::Borrowed("Foo")));
+
+        let normalizer = Normalizer::new([Normalization::Nfkc, Normalization::Lowercase]);
+        assert_eq!(normalizer.steps().len(), 2);
+        assert_eq!(normalizer.normalize("ＦＯＯ"), "foo");
+        assert!(matches!(normalizer.normalize("foo"), Cow::Borrowed(_)));
+        let normalizer = Normalizer::from(Normalization::StripAccents);
+        assert_eq!(normalizer.normalize("Crème Brûlée"), "Creme Brulee");
+    }
+}
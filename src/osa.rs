@@ -0,0 +1,289 @@
+//! Optimal string alignment distance and automaton
+
+use crate::levenshtein::{
+    pattern_first, AutomatonState, LevenshteinAutomaton, LevenshteinBitvector, LevenshteinBlocked,
+    LevenshteinState,
+};
+
+/// Automaton for the optimal string alignment (restricted Damerau-Levenshtein)
+/// distance, counting adjacent transpositions as a single edit
+#[derive(Debug, Clone)]
+pub struct OsaAutomaton<'a> {
+    m: LevenshteinAutomaton<'a>,
+}
+
+impl<'a> OsaAutomaton<'a> {
+    /// Create the automaton for string
+    pub fn new(string: &'a str) -> Self {
+        Self {
+            m: LevenshteinAutomaton::new(string),
+        }
+    }
+
+    fn new_assume_len(string: &'a str, len: usize) -> Self {
+        Self {
+            m: LevenshteinAutomaton::new_assume_len(string, len),
+        }
+    }
+
+    /// State before reading any characters
+    pub fn start(&self) -> OsaState<'_> {
+        match self.m.start() {
+            LevenshteinState::Bitvector(lev) => {
+                OsaState::Bitvector(OsaBitvector { lev, d0: 0, pm: 0 })
+            }
+            LevenshteinState::Blocked(lev) => OsaState::Blocked(OsaBlocked {
+                d0: vec![0; lev.vp.len()],
+                pm: vec![0; lev.vp.len()],
+                lev,
+            }),
+            LevenshteinState::General(_) => unreachable!("start is always a bitvector"),
+        }
+    }
+
+    /// Distance from the string of the automaton to other
+    pub fn distance(&self, other: &str) -> u32 {
+        let mut state = self.start();
+        for value in other.chars() {
+            state.step_mut(value);
+        }
+        state.distance()
+    }
+}
+
+/// State of an optimal string alignment automaton
+#[derive(Debug, Clone)]
+pub enum OsaState<'a> {
+    /// Bitvector for strings up to 64 characters
+    Bitvector(OsaBitvector<'a>),
+    /// Bitvector blocks for strings longer than 64 characters
+    Blocked(OsaBlocked<'a>),
+}
+
+impl AutomatonState for OsaState<'_> {
+    fn step_mut(&mut self, value: char) {
+        match self {
+            Self::Bitvector(s) => s.step_mut(value),
+            Self::Blocked(s) => s.step_mut(value),
+        }
+    }
+
+    fn step(&self, value: char) -> Self {
+        match self {
+            Self::Bitvector(s) => Self::Bitvector(s.step(value)),
+            Self::Blocked(s) => Self::Blocked(s.step(value)),
+        }
+    }
+
+    fn distance(&self) -> u32 {
+        match self {
+            Self::Bitvector(s) => s.distance(),
+            Self::Blocked(s) => s.distance(),
+        }
+    }
+
+    fn can_match(&self, max_edits: u32) -> bool {
+        match self {
+            Self::Bitvector(s) => s.can_match(max_edits),
+            Self::Blocked(s) => s.can_match(max_edits),
+        }
+    }
+}
+
+/// Single bitvector state of an optimal string alignment automaton
+#[derive(Debug, Clone, Copy)]
+pub struct OsaBitvector<'a> {
+    lev: LevenshteinBitvector<'a>,
+    // Diagonal zero and match vectors from the previous step
+    d0: u64,
+    pm: u64,
+}
+
+impl AutomatonState for OsaBitvector<'_> {
+    fn step_mut(&mut self, value: char) {
+        // Myers with transpositions as described by Hyyro
+        let lev = &mut self.lev;
+        let pm = lev.m.match_vector(value);
+        let tr = ((!self.d0 & pm) << 1) & self.pm;
+        let d0 = (((pm & lev.vp).wrapping_add(lev.vp)) ^ lev.vp) | pm | lev.vn | tr;
+        let hp = (lev.vn | !(d0 | lev.vp)) << 1 | 1;
+        let hn = (d0 & lev.vp) << 1;
+
+        lev.vp = hn | !(d0 | hp);
+        lev.vn = hp & d0;
+        lev.offset += 1;
+        self.d0 = d0;
+        self.pm = pm;
+    }
+
+    fn step(&self, value: char) -> Self {
+        let mut new = *self;
+        new.step_mut(value);
+        new
+    }
+
+    fn distance(&self) -> u32 {
+        self.lev.distance()
+    }
+
+    fn can_match(&self, max_edits: u32) -> bool {
+        // Transpositions only reach back to the previous column and cost at
+        // least as much as the cells they skip, so the minimum still bounds
+        self.lev.can_match(max_edits)
+    }
+}
+
+/// Multiple bitvector blocks state of an optimal string alignment automaton
+#[derive(Debug, Clone)]
+pub struct OsaBlocked<'a> {
+    lev: LevenshteinBlocked<'a>,
+    d0: Vec<u64>,
+    pm: Vec<u64>,
+}
+
+impl AutomatonState for OsaBlocked<'_> {
+    fn step_mut(&mut self, value: char) {
+        let lev = &mut self.lev;
+        let pms = lev.m.match_blocks(value);
+        let mut hin = 1i32;
+        // Transposition bit shifted in from the previous block
+        let mut tin = 0u64;
+        for i in 0..lev.vp.len() {
+            let mut pm = pms.map_or(0, |x| x[i]);
+            let vp = lev.vp[i];
+            let vn = lev.vn[i];
+            let t = !self.d0[i] & pm;
+            let tr = ((t << 1) | tin) & self.pm[i];
+            tin = t >> 63;
+            self.pm[i] = pm;
+            let xv = pm | vn | tr;
+            if hin < 0 {
+                pm |= 1;
+            }
+            let xh = (((pm & vp).wrapping_add(vp)) ^ vp) | pm | tr;
+            let mut hp = vn | !(xh | vp);
+            let mut hn = xh & vp;
+            let hout = (hp >> 63) as i32 - (hn >> 63) as i32;
+            hp <<= 1;
+            hn <<= 1;
+            if hin > 0 {
+                hp |= 1;
+            } else if hin < 0 {
+                hn |= 1;
+            }
+            lev.vp[i] = hn | !(xv | hp);
+            lev.vn[i] = hp & xv;
+            self.d0[i] = xh | xv;
+            hin = hout;
+        }
+        lev.offset += 1;
+    }
+
+    fn step(&self, value: char) -> Self {
+        let mut new = self.clone();
+        new.step_mut(value);
+        new
+    }
+
+    fn distance(&self) -> u32 {
+        self.lev.distance()
+    }
+
+    fn can_match(&self, max_edits: u32) -> bool {
+        self.lev.can_match(max_edits)
+    }
+}
+
+/// Find the optimal string alignment distance between two strings
+///
+/// Also known as the restricted Damerau-Levenshtein distance, where adjacent
+/// transpositions count as a single edit but substrings are not edited twice
+pub fn osa(a: &str, b: &str) -> u32 {
+    if a == b {
+        return 0;
+    }
+    let (a, len_a, b) = pattern_first(a, b);
+    let automaton = OsaAutomaton::new_assume_len(a, len_a);
+    automaton.distance(b)
+}
+
+/// Find the restricted Damerau-Levenshtein distance between two strings
+///
+/// Same as [`osa`]
+pub fn damerau_levenshtein(a: &str, b: &str) -> u32 {
+    osa(a, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn osa_matrix(a: &str, b: &str) -> Vec<Vec<u32>> {
+        let a: Vec<char> = a.chars().collect();
+        let b: Vec<char> = b.chars().collect();
+        let mut d = vec![vec![0; a.len() + 1]; b.len() + 1];
+        for (j, col) in d.iter_mut().enumerate() {
+            col[0] = j as u32;
+        }
+        for (i, x) in d[0].iter_mut().enumerate() {
+            *x = i as u32;
+        }
+        for j in 1..=b.len() {
+            for i in 1..=a.len() {
+                let sub = d[j - 1][i - 1] + (a[i - 1] != b[j - 1]) as u32;
+                let mut x = sub.min(d[j - 1][i] + 1).min(d[j][i - 1] + 1);
+                if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                    x = x.min(d[j - 2][i - 2] + 1);
+                }
+                d[j][i] = x;
+            }
+        }
+        d
+    }
+
+    #[test]
+    fn distances() {
+        assert_eq!(osa("teh", "the"), 1);
+        assert_eq!(osa("ca", "abc"), 3);
+        assert_eq!(osa("abcdef", "badcfe"), 3);
+        assert_eq!(osa("foo", ""), 3);
+        assert_eq!(osa("", ""), 0);
+        assert_eq!(damerau_levenshtein("kitten", "sitting"), 3);
+
+        let a = "ab".repeat(50);
+        let b = "ba".repeat(50);
+        assert_eq!(osa(&a, &b), 2);
+        assert_eq!(osa(&a, &a[1..]), 1);
+    }
+
+    #[test]
+    fn automaton() {
+        // Deterministic pseudo-random strings over a small alphabet
+        let mut seed = 54321u64;
+        let mut random_string = |len: usize| {
+            (0..len)
+                .map(|_| {
+                    seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+                    (b'a' + (seed >> 60) as u8 % 3) as char
+                })
+                .collect::<String>()
+        };
+        for (len_a, len_b) in [(0, 5), (10, 12), (64, 64), (65, 60), (130, 140), (200, 20)] {
+            let a = random_string(len_a);
+            let b = random_string(len_b);
+            let matrix = osa_matrix(&a, &b);
+            let automaton = OsaAutomaton::new(&a);
+            let mut state = automaton.start();
+            for (j, c) in b.chars().enumerate() {
+                state.step_mut(c);
+                let col = &matrix[j + 1];
+                assert_eq!(state.distance(), col[len_a]);
+                let min = *col.iter().min().unwrap();
+                assert!(state.can_match(min));
+                assert!(min == 0 || !state.can_match(min - 1));
+            }
+            assert_eq!(osa(&a, &b), matrix[len_b][len_a]);
+            assert_eq!(osa(&b, &a), matrix[len_b][len_a]);
+        }
+    }
+}
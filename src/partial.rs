@@ -0,0 +1,177 @@
+//! Approximate matching of a pattern within a longer text
+//!
+//! The pattern may match any substring of the text, so the distance at each
+//! end position is the lowest distance of the pattern to a substring ending
+//! there. This is Myers' bitvector algorithm with a free start position.
+
+use crate::levenshtein::{AutomatonState, LevenshteinAutomaton};
+
+/// Call f with each end position in text and the lowest distance of pattern
+/// to a substring ending there, starting from the empty prefix
+fn ends(pattern: &LevenshteinAutomaton, text: &str, mut f: impl FnMut(usize, u32)) {
+    let mut state = pattern.start_free();
+    f(0, state.distance());
+    for (i, c) in text.char_indices() {
+        state.step_mut(c);
+        f(i + c.len_utf8(), state.distance());
+    }
+}
+
+/// Find approximate occurrences of pattern in text within max_edits
+///
+/// Returns (start, end, distance) for every end position where a substring
+/// of text is within max_edits of pattern, with the distance the lowest of
+/// any substring ending there and start that of the longest such substring.
+/// Positions are byte offsets into text, sorted by end.
+pub fn fuzzy_find(pattern: &str, text: &str, max_edits: u32) -> Vec<(usize, usize, u32)> {
+    let automaton = LevenshteinAutomaton::new(pattern);
+    let mut found = Vec::new();
+    ends(&automaton, text, |end, distance| {
+        if distance <= max_edits {
+            found.push((end, distance));
+        }
+    });
+    if found.is_empty() {
+        return Vec::new();
+    }
+
+    // Read the text backwards from each end with the reversed pattern
+    let reversed: String = pattern.chars().rev().collect();
+    let reversed = LevenshteinAutomaton::new(&reversed);
+    found
+        .into_iter()
+        .map(|(end, distance)| {
+            let mut start = end;
+            let mut state = reversed.start();
+            for (i, c) in text[..end].char_indices().rev() {
+                state.step_mut(c);
+                if state.distance() == distance {
+                    start = i;
+                }
+                if !state.can_match(distance) {
+                    break;
+                }
+            }
+            (start, end, distance)
+        })
+        .collect()
+}
+
+/// Similarity of the shorter string to its best matching substring of the other
+///
+/// Defined as `1 - distance / len`, where distance is the lowest distance of
+/// the shorter string to any substring of the longer and len its length, and
+/// 1 if the shorter string is empty.
+pub fn partial_ratio(a: &str, b: &str) -> f64 {
+    let (len_a, len_b) = (a.chars().count(), b.chars().count());
+    let (pattern, len, text) = if len_a <= len_b {
+        (a, len_a, b)
+    } else {
+        (b, len_b, a)
+    };
+    if len == 0 {
+        return 1.0;
+    }
+    let automaton = LevenshteinAutomaton::new_assume_len(pattern, len);
+    let mut best = u32::MAX;
+    ends(&automaton, text, |_, distance| best = best.min(distance));
+    1.0 - best as f64 / len as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::levenshtein::levenshtein;
+
+    /// Lowest distance of pattern to a substring of text ending at each char
+    fn brute(pattern: &str, text: &str) -> Vec<(usize, u32)> {
+        let bounds: Vec<_> = text
+            .char_indices()
+            .map(|x| x.0)
+            .chain([text.len()])
+            .collect();
+        bounds
+            .iter()
+            .map(|&end| {
+                let distance = bounds
+                    .iter()
+                    .take_while(|&&start| start <= end)
+                    .map(|&start| levenshtein(pattern, &text[start..end]))
+                    .min()
+                    .unwrap();
+                (end, distance)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn find() {
+        assert_eq!(
+            fuzzy_find("acme", "Contact ACME or acme corp", 1),
+            vec![(16, 19, 1), (16, 20, 0), (16, 21, 1)]
+        );
+        assert_eq!(fuzzy_find("foo", "xxfoxx", 0), vec![]);
+        assert_eq!(fuzzy_find("foo", "xxfoxx", 1), vec![(2, 4, 1), (2, 5, 1)]);
+        assert_eq!(
+            fuzzy_find("", "ab", 0),
+            vec![(0, 0, 0), (1, 1, 0), (2, 2, 0)]
+        );
+        assert_eq!(fuzzy_find("ab", "", 2), vec![(0, 0, 2)]);
+        assert_eq!(fuzzy_find("café", "un café noir", 0), vec![(3, 8, 0)]);
+        let text = "xy".repeat(50) + &"abc".repeat(30) + "zz";
+        let pattern = "abc".repeat(30);
+        assert_eq!(fuzzy_find(&pattern, &text, 0), vec![(100, 190, 0)]);
+        assert_eq!(fuzzy_find(&pattern, &text, 1).len(), 3);
+    }
+
+    #[test]
+    fn ends_brute() {
+        let texts = [
+            "",
+            "a",
+            "abcabcab",
+            "the quick brown fox",
+            "aaaaaa",
+            "bacabacab",
+            "naïve café",
+        ];
+        let long = "abcdefghij".repeat(8);
+        let patterns = [
+            "a",
+            "ab",
+            "abc",
+            "cab",
+            "quack",
+            "xyz",
+            "aaa",
+            "cafe",
+            &long[3..70],
+        ];
+        for pattern in patterns {
+            let automaton = LevenshteinAutomaton::new(pattern);
+            for text in texts.iter().copied().chain([long.as_str()]) {
+                let mut found = Vec::new();
+                ends(&automaton, text, |end, distance| {
+                    found.push((end, distance))
+                });
+                assert_eq!(found, brute(pattern, text), "{pattern} {text}");
+                for (start, end, distance) in fuzzy_find(pattern, text, 2) {
+                    assert_eq!(levenshtein(pattern, &text[start..end]), distance);
+                    assert!((0..start)
+                        .filter(|&x| text.is_char_boundary(x))
+                        .all(|x| levenshtein(pattern, &text[x..end]) > distance));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ratio() {
+        assert_eq!(partial_ratio("acme", "Contact acme today"), 1.0);
+        assert_eq!(partial_ratio("Contact acme today", "acme"), 1.0);
+        assert_eq!(partial_ratio("acme", "Contact acne today"), 0.75);
+        assert_eq!(partial_ratio("", "foo"), 1.0);
+        assert_eq!(partial_ratio("foo", ""), 1.0);
+        assert_eq!(partial_ratio("abc", "xyz"), 0.0);
+    }
+}
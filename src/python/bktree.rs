@@ -0,0 +1,349 @@
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyType};
+use rayon::prelude::*;
+use std::path::PathBuf;
+
+use super::metric::PyMetric;
+use super::{extract_items, pickle_load, pickle_save, similarity_bound};
+use crate::bktree::{BKTree, BKTreeMap};
+use crate::metric::Metric;
+use crate::segmentation::{Segmentation, Segmented};
+
+/// Metric counting edits in the units of segmentation
+fn segmented(metric: PyMetric, segmentation: Segmentation) -> PyResult<Segmented<PyMetric>> {
+    if matches!(metric, PyMetric::Callable { .. }) && segmentation != Segmentation::Codepoints {
+        return Err(PyValueError::new_err(
+            "segmentation is only supported for built-in metrics",
+        ));
+    }
+    Ok(Segmented::new(metric, segmentation))
+}
+
+/// BK-tree storing the strings to search against
+///
+/// The metric is either the name of a built-in metric or a callable taking two
+/// strings and returning a non-negative integer distance. With segmentation
+/// "graphemes", built-in metrics count edits in grapheme clusters rather than
+/// codepoints.
+#[pyclass(name = "BKTree", module = "assrs")]
+#[derive(Debug, Default, Clone)]
+pub struct PyBKTree(BKTree<Segmented<PyMetric>>);
+
+#[pymethods]
+impl PyBKTree {
+    #[new]
+    #[pyo3(signature = (
+        items=None,
+        metric=PyMetric::Levenshtein,
+        segmentation=Segmentation::Codepoints,
+    ))]
+    fn py_new(
+        items: Option<Vec<String>>,
+        metric: PyMetric,
+        segmentation: Segmentation,
+    ) -> PyResult<Self> {
+        let mut tree = BKTree::with_metric(segmented(metric, segmentation)?);
+        tree.extend(items.into_iter().flatten());
+        tree.metric().metric.take_error()?;
+        Ok(Self(tree))
+    }
+
+    #[staticmethod]
+    fn new() -> Self {
+        Self::default()
+    }
+
+    fn insert(&mut self, value: String) -> PyResult<()> {
+        self.0.insert(value);
+        self.0.metric().metric.take_error()
+    }
+
+    fn remove(&mut self, value: &str) -> PyResult<bool> {
+        let result = self.0.remove(value);
+        self.0.metric().metric.take_error()?;
+        Ok(result)
+    }
+
+    fn get(&self, value: &str) -> PyResult<Option<&str>> {
+        let result = self.0.get(value);
+        self.0.metric().metric.take_error()?;
+        Ok(result)
+    }
+
+    fn contains(&self, value: &str) -> PyResult<bool> {
+        Ok(self.get(value)?.is_some())
+    }
+
+    fn values(&self) -> Vec<&str> {
+        self.0.values()
+    }
+
+    /// Serialize the BK-tree to bytes, with the built-in metric and segmentation
+    fn to_bytes<'py>(&self, py: Python<'py>) -> &'py PyBytes {
+        PyBytes::new(py, &self.0.to_bytes())
+    }
+
+    /// Deserialize a BK-tree from bytes created by to_bytes
+    ///
+    /// The metric and segmentation are those stored unless given, and must
+    /// match them if they are. A callable metric is not stored, so it must be
+    /// given again.
+    #[staticmethod]
+    #[pyo3(signature = (data, metric=None, segmentation=None))]
+    fn from_bytes(
+        data: &[u8],
+        metric: Option<PyMetric>,
+        segmentation: Option<Segmentation>,
+    ) -> PyResult<Self> {
+        let (stored, stored_segmentation) = BKTree::stored_metric(data)?;
+        let metric = match (metric, stored) {
+            (Some(metric), _) => metric,
+            (None, Some(stored)) => stored.into(),
+            (None, None) => {
+                return Err(PyValueError::new_err(
+                    "BK-tree was saved with a callable metric, which must be given",
+                ))
+            }
+        };
+        let metric = segmented(metric, segmentation.unwrap_or(stored_segmentation))?;
+        Ok(Self(BKTree::from_bytes_with_metric(data, metric)?))
+    }
+
+    /// Save the BK-tree to a file, with the built-in metric and segmentation
+    fn save(&self, path: PathBuf) -> PyResult<()> {
+        Ok(std::fs::write(path, self.0.to_bytes())?)
+    }
+
+    /// Load a BK-tree from a file, with the metric and segmentation as for from_bytes
+    #[staticmethod]
+    #[pyo3(signature = (path, metric=None, segmentation=None))]
+    fn load(
+        path: PathBuf,
+        metric: Option<PyMetric>,
+        segmentation: Option<Segmentation>,
+    ) -> PyResult<Self> {
+        Self::from_bytes(&std::fs::read(path)?, metric, segmentation)
+    }
+
+    fn __getstate__<'py>(&self, py: Python<'py>) -> &'py PyBytes {
+        self.to_bytes(py)
+    }
+
+    fn __setstate__(&mut self, state: &[u8]) -> PyResult<()> {
+        self.0 = BKTree::from_bytes_with_metric(state, self.0.metric().clone())?;
+        Ok(())
+    }
+
+    fn __reduce__<'py>(&self, py: Python<'py>) -> (&'py PyType, PyObject, &'py PyBytes) {
+        let metric = self.0.metric();
+        let args = (py.None(), &metric.metric, metric.segmentation);
+        (py.get_type::<Self>(), args.to_object(py), self.to_bytes(py))
+    }
+
+    /// Find best match in BK-tree for query
+    ///
+    /// Matches can also be required to have at least min_similarity to query
+    #[pyo3(signature = (query, max_edits=None, min_similarity=None))]
+    fn find_one(
+        &self,
+        py: Python<'_>,
+        query: &str,
+        max_edits: Option<u32>,
+        min_similarity: Option<f64>,
+    ) -> PyResult<Option<(&str, u32)>> {
+        let bound = similarity_bound(self.0.metric().len(query), max_edits, min_similarity)?;
+        let result = py.allow_threads(|| match bound {
+            Some(bound) => self.0.find_one_within(query, &bound),
+            None => self.0.find_one(query, max_edits),
+        });
+        self.0.metric().metric.take_error()?;
+        Ok(result)
+    }
+
+    /// Find best match in BK-tree for each query, in parallel
+    #[pyo3(signature = (queries, max_edits=None))]
+    fn find_one_batch(
+        &self,
+        py: Python<'_>,
+        queries: Vec<&str>,
+        max_edits: Option<u32>,
+    ) -> PyResult<Vec<Option<(&str, u32)>>> {
+        let result = py.allow_threads(|| {
+            queries
+                .par_iter()
+                .map(|query| self.0.find_one(query, max_edits))
+                .collect()
+        });
+        self.0.metric().metric.take_error()?;
+        Ok(result)
+    }
+
+    /// Find all matches in BK-tree within max_edits of query
+    ///
+    /// Sorted by distance and then value
+    fn find_all(&self, py: Python<'_>, query: &str, max_edits: u32) -> PyResult<Vec<(&str, u32)>> {
+        let result = py.allow_threads(|| self.0.find_all(query, max_edits));
+        self.0.metric().metric.take_error()?;
+        Ok(result)
+    }
+
+    /// Find the k best matches in BK-tree for query
+    ///
+    /// Sorted by distance and then value
+    fn find_k(
+        &self,
+        py: Python<'_>,
+        query: &str,
+        k: usize,
+        max_edits: Option<u32>,
+    ) -> PyResult<Vec<(&str, u32)>> {
+        let result = py.allow_threads(|| self.0.find_k(query, k, max_edits));
+        self.0.metric().metric.take_error()?;
+        Ok(result)
+    }
+}
+
+/// BK-tree storing strings with an associated payload to search against
+///
+/// The metric is either the name of a built-in metric or a callable taking two
+/// strings and returning a non-negative integer distance. With segmentation
+/// "graphemes", built-in metrics count edits in grapheme clusters rather than
+/// codepoints.
+#[pyclass(name = "BKTreeMap", module = "assrs")]
+#[derive(Debug, Default, Clone)]
+pub struct PyBKTreeMap(BKTreeMap<PyObject, Segmented<PyMetric>>);
+
+#[pymethods]
+impl PyBKTreeMap {
+    #[new]
+    #[pyo3(signature = (
+        items=None,
+        metric=PyMetric::Levenshtein,
+        segmentation=Segmentation::Codepoints,
+    ))]
+    fn py_new(
+        items: Option<&PyAny>,
+        metric: PyMetric,
+        segmentation: Segmentation,
+    ) -> PyResult<Self> {
+        let items = items.map(extract_items).transpose()?;
+        let mut tree = BKTreeMap::with_metric(segmented(metric, segmentation)?);
+        tree.extend(items.into_iter().flatten());
+        tree.metric().metric.take_error()?;
+        Ok(Self(tree))
+    }
+
+    #[staticmethod]
+    fn new() -> Self {
+        Self::default()
+    }
+
+    /// Insert key with payload, returning any previous payload
+    fn insert(&mut self, key: String, value: PyObject) -> PyResult<Option<PyObject>> {
+        let result = self.0.insert(key, value);
+        self.0.metric().metric.take_error()?;
+        Ok(result)
+    }
+
+    /// Remove key, returning whether it was present
+    fn remove(&mut self, key: &str) -> PyResult<bool> {
+        let result = self.0.remove(key);
+        self.0.metric().metric.take_error()?;
+        Ok(result.is_some())
+    }
+
+    /// Get (key, 0, payload) for key
+    fn get(&self, key: &str) -> PyResult<Option<(&str, u32, &PyObject)>> {
+        let result = self.0.get(key);
+        self.0.metric().metric.take_error()?;
+        Ok(result.map(|(k, v)| (k, 0, v)))
+    }
+
+    fn contains(&self, key: &str) -> PyResult<bool> {
+        Ok(self.get(key)?.is_some())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.iter().map(|x| x.0).collect()
+    }
+
+    fn values(&self) -> Vec<&PyObject> {
+        self.0.iter().map(|x| x.1).collect()
+    }
+
+    fn items(&self) -> Vec<(&str, &PyObject)> {
+        self.0.iter().collect()
+    }
+
+    /// Save the BK-tree to a file, pickling the metric and payloads
+    fn save(slf: &PyCell<Self>, path: PathBuf) -> PyResult<()> {
+        pickle_save(slf, path)
+    }
+
+    /// Load a BK-tree saved with save
+    ///
+    /// Loading unpickles the file, so only load files from trusted sources.
+    #[staticmethod]
+    fn load(py: Python<'_>, path: PathBuf) -> PyResult<Self> {
+        pickle_load(py, path)?.extract()
+    }
+
+    /// Pickle as the items in traversal order, which inserted in order
+    /// recreate the same tree
+    fn __reduce__<'py>(&self, py: Python<'py>) -> (&'py PyType, PyObject) {
+        let items: Vec<_> = self.0.iter().collect();
+        let metric = self.0.metric();
+        let args = (items, &metric.metric, metric.segmentation);
+        (py.get_type::<Self>(), args.to_object(py))
+    }
+
+    /// Find best match in BK-tree for query
+    ///
+    /// Matches can also be required to have at least min_similarity to query
+    #[pyo3(signature = (query, max_edits=None, min_similarity=None))]
+    fn find_one(
+        &self,
+        py: Python<'_>,
+        query: &str,
+        max_edits: Option<u32>,
+        min_similarity: Option<f64>,
+    ) -> PyResult<Option<(&str, u32, &PyObject)>> {
+        let bound = similarity_bound(self.0.metric().len(query), max_edits, min_similarity)?;
+        let result = py.allow_threads(|| match bound {
+            Some(bound) => self.0.find_one_within(query, &bound),
+            None => self.0.find_one(query, max_edits),
+        });
+        self.0.metric().metric.take_error()?;
+        Ok(result)
+    }
+
+    /// Find all matches in BK-tree within max_edits of query
+    ///
+    /// Sorted by distance and then key
+    fn find_all(
+        &self,
+        py: Python<'_>,
+        query: &str,
+        max_edits: u32,
+    ) -> PyResult<Vec<(&str, u32, &PyObject)>> {
+        let result = py.allow_threads(|| self.0.find_all(query, max_edits));
+        self.0.metric().metric.take_error()?;
+        Ok(result)
+    }
+
+    /// Find the k best matches in BK-tree for query
+    ///
+    /// Sorted by distance and then key
+    fn find_k(
+        &self,
+        py: Python<'_>,
+        query: &str,
+        k: usize,
+        max_edits: Option<u32>,
+    ) -> PyResult<Vec<(&str, u32, &PyObject)>> {
+        let result = py.allow_threads(|| self.0.find_k(query, k, max_edits));
+        self.0.metric().metric.take_error()?;
+        Ok(result)
+    }
+}
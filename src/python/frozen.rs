@@ -0,0 +1,129 @@
+use memmap2::Mmap;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use std::path::PathBuf;
+
+use super::metric::{MetricAutomaton, PyMetric};
+use super::trie::PyTrie;
+use crate::frozen::FrozenTrie;
+
+/// Bytes of a frozen trie, in memory or mapped from a file
+#[derive(Debug)]
+pub enum Data {
+    Owned(Vec<u8>),
+    Mapped(Mmap),
+}
+
+impl AsRef<[u8]> for Data {
+    fn as_ref(&self) -> &[u8] {
+        match self {
+            Self::Owned(data) => data,
+            Self::Mapped(data) => data,
+        }
+    }
+}
+
+impl From<FrozenTrie> for PyFrozenTrie {
+    fn from(frozen: FrozenTrie) -> Self {
+        Self(FrozenTrie::from_bytes(Data::Owned(frozen.into_data())).unwrap())
+    }
+}
+
+/// Immutable trie storing the strings to search against, with shared suffixes
+#[pyclass(name = "FrozenTrie", module = "assrs")]
+#[derive(Debug)]
+pub struct PyFrozenTrie(FrozenTrie<Data>);
+
+#[pymethods]
+impl PyFrozenTrie {
+    #[new]
+    fn py_new(mut items: Vec<&str>) -> PyResult<Self> {
+        items.sort_unstable();
+        Ok(FrozenTrie::from_sorted_iter(items)?.into())
+    }
+
+    /// Create a frozen trie from values in sorted order
+    #[staticmethod]
+    fn from_sorted(items: Vec<&str>) -> PyResult<Self> {
+        Ok(FrozenTrie::from_sorted_iter(items)?.into())
+    }
+
+    /// Create a frozen trie with the values of a trie
+    ///
+    /// Raises ValueError if the trie has grapheme segmentation or a normalizer
+    #[staticmethod]
+    fn from_trie(trie: &PyTrie) -> PyResult<Self> {
+        Ok(FrozenTrie::try_from(&trie.0)?.into())
+    }
+
+    /// Serialize the frozen trie to bytes
+    fn to_bytes<'py>(&self, py: Python<'py>) -> &'py PyBytes {
+        PyBytes::new(py, self.0.as_bytes())
+    }
+
+    /// Deserialize a frozen trie from bytes
+    #[staticmethod]
+    fn from_bytes(data: Vec<u8>) -> PyResult<Self> {
+        Ok(Self(FrozenTrie::from_bytes(Data::Owned(data))?))
+    }
+
+    /// Save the frozen trie to a file
+    fn save(&self, path: PathBuf) -> PyResult<()> {
+        Ok(self.0.save(path)?)
+    }
+
+    /// Open a frozen trie saved to a file by memory mapping it
+    ///
+    /// The file must not be modified while it is open
+    #[staticmethod]
+    fn open(path: PathBuf) -> PyResult<Self> {
+        let file = std::fs::File::open(path)?;
+        // Safety: the mapping is only read, and the file is not to be modified
+        let mmap = unsafe { Mmap::map(&file)? };
+        Ok(Self(FrozenTrie::from_bytes(Data::Mapped(mmap))?))
+    }
+
+    fn __len__(&self) -> usize {
+        self.0.len()
+    }
+
+    fn get<'a>(&self, value: &'a str) -> Option<&'a str> {
+        self.0.get(value)
+    }
+
+    fn contains(&self, value: &str) -> bool {
+        self.0.contains(value)
+    }
+
+    fn values(&self) -> Vec<String> {
+        self.0.values()
+    }
+
+    /// Find best match in frozen trie for query
+    #[pyo3(signature = (query, max_edits=None, metric=PyMetric::Levenshtein))]
+    fn find_one(
+        &self,
+        py: Python<'_>,
+        query: &str,
+        max_edits: Option<u32>,
+        metric: PyMetric,
+    ) -> PyResult<Option<(String, u32)>> {
+        let automaton = MetricAutomaton::new(query, &metric)?;
+        Ok(py.allow_threads(|| self.0.find_one_with(&automaton.start(), max_edits)))
+    }
+
+    /// Find all matches in frozen trie within max_edits of query
+    ///
+    /// Sorted by distance and then value
+    #[pyo3(signature = (query, max_edits, metric=PyMetric::Levenshtein))]
+    fn find_all(
+        &self,
+        py: Python<'_>,
+        query: &str,
+        max_edits: u32,
+        metric: PyMetric,
+    ) -> PyResult<Vec<(String, u32)>> {
+        let automaton = MetricAutomaton::new(query, &metric)?;
+        Ok(py.allow_threads(|| self.0.find_all_with(&automaton.start(), max_edits)))
+    }
+}
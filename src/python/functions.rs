@@ -0,0 +1,286 @@
+use pyo3::prelude::*;
+use pyo3::types::PyByteArray;
+use rayon::prelude::*;
+use std::borrow::Cow;
+
+use super::weighted::PySubstitutions;
+use super::{char_indices, check_similarity, iter_strs};
+use crate::levenshtein::{levenshtein_within, LevenshteinAutomaton};
+use crate::segmentation::{Graphemes, Segmentation};
+use crate::weighted::{WeightedAutomaton, Weights};
+
+/// Find the Levenshtein distance between two strings
+///
+/// Weights are the costs of (insert, delete, substitute) transforming a into b,
+/// with substitution costs taken from substitutions if given. Returns None if
+/// the distance exceeds score_cutoff, stopping as soon as it must. Edits are
+/// counted in grapheme clusters rather than codepoints with segmentation
+/// "graphemes", where substitutions only apply to single codepoints.
+#[pyfunction]
+#[pyo3(signature = (
+    a,
+    b,
+    weights=(1, 1, 1),
+    substitutions=None,
+    score_cutoff=None,
+    segmentation=Segmentation::Codepoints,
+))]
+pub fn levenshtein(
+    a: &str,
+    b: &str,
+    weights: (u32, u32, u32),
+    substitutions: Option<PyRef<PySubstitutions>>,
+    score_cutoff: Option<u32>,
+    segmentation: Segmentation,
+) -> PyResult<Option<u32>> {
+    let (a, b) = match segmentation {
+        Segmentation::Codepoints => (Cow::Borrowed(a), Cow::Borrowed(b)),
+        Segmentation::Graphemes => {
+            let mut graphemes = Graphemes::new();
+            let a = graphemes.intern(a)?;
+            (Cow::Owned(a), graphemes.lookup(b))
+        }
+    };
+    let (a, b) = (a.as_ref(), b.as_ref());
+    let (insert, delete, substitute) = weights;
+    let weights = Weights::new(insert, delete, substitute);
+    let automaton = match substitutions.as_deref() {
+        Some(table) => WeightedAutomaton::with_substitutions(a, weights, &table.0),
+        None if weights == Weights::default() => {
+            return Ok(match score_cutoff {
+                Some(max_edits) => levenshtein_within(a, b, max_edits),
+                None => Some(crate::levenshtein::levenshtein(a, b)),
+            });
+        }
+        None => WeightedAutomaton::new(a, weights),
+    };
+    Ok(match score_cutoff {
+        Some(max_edits) => automaton.distance_within(b, max_edits),
+        None => Some(automaton.distance(b)),
+    })
+}
+
+/// Find the normalized Levenshtein similarity between two strings
+///
+/// Defined as 1 - distance / max(len(a), len(b)), and 1 for two empty strings
+#[pyfunction]
+pub fn levenshtein_similarity(a: &str, b: &str) -> f64 {
+    crate::similarity::levenshtein_similarity(a, b)
+}
+
+/// Find the best match in a list of choices
+///
+/// Returns (choice, distance, index) or None (for empty choices, or none with
+/// at least min_similarity if given)
+#[pyfunction]
+#[pyo3(signature = (query, choices, min_similarity=None))]
+pub fn levenshtein_extract(
+    query: &str,
+    choices: Vec<&str>,
+    min_similarity: Option<f64>,
+) -> PyResult<Option<(String, u32, usize)>> {
+    check_similarity(min_similarity)?;
+    Ok(match min_similarity {
+        Some(min) => crate::levenshtein::levenshtein_extract_similar(query, choices, min),
+        None => crate::levenshtein::levenshtein_extract(query, choices),
+    })
+}
+
+/// Find the k best matches in an iterable of choices
+///
+/// Returns a list of (choice, distance, index) sorted by distance and then index
+#[pyfunction]
+pub fn levenshtein_extract_k(
+    query: &str,
+    choices: &PyAny,
+    k: usize,
+) -> PyResult<Vec<(String, u32, usize)>> {
+    let mut error = None;
+    let choices = iter_strs(choices, &mut error)?;
+    let result = crate::levenshtein::levenshtein_extract_k(query, choices, k);
+    error.map_or(Ok(result), Err)
+}
+
+/// Find all matches in an iterable of choices within max_edits of query
+///
+/// Returns a list of (choice, distance, index) sorted by distance and then index
+#[pyfunction]
+pub fn levenshtein_extract_all(
+    query: &str,
+    choices: &PyAny,
+    max_edits: u32,
+) -> PyResult<Vec<(String, u32, usize)>> {
+    let mut error = None;
+    let choices = iter_strs(choices, &mut error)?;
+    let result = crate::levenshtein::levenshtein_extract_all(query, choices, max_edits);
+    error.map_or(Ok(result), Err)
+}
+
+/// Find the best match in a list of choices for each query, in parallel
+///
+/// Returns a list of (choice, distance, index) or None, in the order of queries
+#[pyfunction]
+pub fn levenshtein_extract_batch(
+    py: Python<'_>,
+    queries: Vec<&str>,
+    choices: Vec<&str>,
+) -> Vec<Option<(String, u32, usize)>> {
+    py.allow_threads(|| {
+        queries
+            .par_iter()
+            .map(|query| crate::levenshtein::levenshtein_extract(query, &choices))
+            .collect()
+    })
+}
+
+/// Find the Levenshtein distance between each query and each choice, in parallel
+///
+/// Returns a NumPy array of uint32 with a row for each query if NumPy is
+/// available, otherwise a list of lists. Distances over max_edits are reported
+/// as max_edits + 1.
+#[pyfunction]
+#[pyo3(signature = (queries, choices, max_edits=None))]
+pub fn cdist(
+    py: Python<'_>,
+    queries: Vec<&str>,
+    choices: Vec<&str>,
+    max_edits: Option<u32>,
+) -> PyResult<PyObject> {
+    let max_edits = max_edits.unwrap_or(u32::MAX);
+    let rows: Vec<Vec<u32>> = py.allow_threads(|| {
+        queries
+            .par_iter()
+            .map(|query| {
+                let automaton = LevenshteinAutomaton::new(query);
+                choices
+                    .iter()
+                    .map(|x| {
+                        let distance = automaton.distance_within(x, max_edits);
+                        distance.unwrap_or(max_edits.saturating_add(1))
+                    })
+                    .collect()
+            })
+            .collect()
+    });
+    let Ok(numpy) = py.import("numpy") else {
+        return Ok(rows.to_object(py));
+    };
+    let bytes: Vec<u8> = rows
+        .iter()
+        .flatten()
+        .flat_map(|x| x.to_ne_bytes())
+        .collect();
+    let array = numpy.call_method1(
+        "frombuffer",
+        (PyByteArray::new(py, &bytes), numpy.getattr("uint32")?),
+    )?;
+    Ok(array
+        .call_method1("reshape", (queries.len(), choices.len()))?
+        .into())
+}
+
+/// Find approximate occurrences of pattern in text within max_edits
+///
+/// Returns a list of (start, end, distance) for every end position where a
+/// substring of text is within max_edits of pattern, with the distance the
+/// lowest of any substring ending there and start that of the longest such
+/// substring, so that text[start:end] is the match. Sorted by end.
+#[pyfunction]
+pub fn fuzzy_find(
+    py: Python<'_>,
+    pattern: &str,
+    text: &str,
+    max_edits: u32,
+) -> Vec<(usize, usize, u32)> {
+    py.allow_threads(|| {
+        let found = crate::partial::fuzzy_find(pattern, text, max_edits);
+        let Some(index) = char_indices(text) else {
+            return found;
+        };
+        found
+            .into_iter()
+            .map(|(start, end, distance)| (index[start], index[end], distance))
+            .collect()
+    })
+}
+
+/// Find the similarity of the shorter string to its best matching substring of the other
+///
+/// Defined as 1 - distance / len, where distance is the lowest Levenshtein
+/// distance of the shorter string to any substring of the longer and len its
+/// length, and 1 if the shorter string is empty
+#[pyfunction]
+pub fn partial_ratio(a: &str, b: &str) -> f64 {
+    crate::partial::partial_ratio(a, b)
+}
+
+/// Edit operation as (tag, src, dest)
+pub(crate) type PyEditop = (&'static str, usize, usize);
+
+/// Find the edit operations transforming a into b
+///
+/// Returns a list of (tag, src, dest) with tag "replace", "insert" or
+/// "delete", src the position in a and dest that in b, as few as the
+/// Levenshtein distance
+#[pyfunction]
+pub fn editops(py: Python<'_>, a: &str, b: &str) -> Vec<PyEditop> {
+    let ops = py.allow_threads(|| crate::editops::editops(a, b));
+    ops.into_iter()
+        .map(|x| (x.kind.as_str(), x.src, x.dest))
+        .collect()
+}
+
+/// Find the ranges of a and b that are equal or edited, transforming a into b
+///
+/// Returns a list of (tag, a_start, a_end, b_start, b_end) covering both
+/// strings, with tag "equal", "replace", "insert" or "delete" as for
+/// difflib.SequenceMatcher.get_opcodes
+#[pyfunction]
+pub fn opcodes(
+    py: Python<'_>,
+    a: &str,
+    b: &str,
+) -> Vec<(&'static str, usize, usize, usize, usize)> {
+    let opcodes = py.allow_threads(|| crate::editops::opcodes(a, b));
+    opcodes
+        .into_iter()
+        .map(|x| {
+            let kind = x.kind.as_str();
+            (kind, x.src_start, x.src_end, x.dest_start, x.dest_end)
+        })
+        .collect()
+}
+
+/// Find the optimal string alignment distance between two strings
+///
+/// Also known as the restricted Damerau-Levenshtein distance, where adjacent
+/// transpositions count as a single edit but substrings are not edited twice
+#[pyfunction]
+pub fn osa(a: &str, b: &str) -> u32 {
+    crate::osa::osa(a, b)
+}
+
+/// Find the restricted Damerau-Levenshtein distance between two strings
+///
+/// Same as osa
+#[pyfunction]
+pub fn damerau_levenshtein(a: &str, b: &str) -> u32 {
+    crate::osa::osa(a, b)
+}
+
+/// Find the normalized optimal string alignment similarity between two strings
+///
+/// Defined as 1 - distance / max(len(a), len(b)), and 1 for two empty strings
+#[pyfunction]
+pub fn osa_similarity(a: &str, b: &str) -> f64 {
+    crate::similarity::osa_similarity(a, b)
+}
+
+/// Find the Hamming distance between two strings
+///
+/// Any difference in length is counted as substitutions of the missing
+/// characters
+#[pyfunction]
+pub fn hamming(a: &str, b: &str) -> u32 {
+    crate::hamming::hamming(a, b)
+}
@@ -0,0 +1,221 @@
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+use std::sync::Mutex;
+
+use crate::levenshtein::{AutomatonState, LevenshteinAutomaton, LevenshteinState};
+use crate::metric::{BuiltinMetric, Hamming, Levenshtein, Metric, Osa};
+use crate::osa::{OsaAutomaton, OsaState};
+use crate::weighted::{Substitutions, WeightedAutomaton, WeightedState, Weights};
+
+/// Metric chosen from Python, by name or as a callable
+#[derive(Debug, Default)]
+pub enum PyMetric {
+    #[default]
+    Levenshtein,
+    Osa,
+    Hamming,
+    Callable {
+        func: PyObject,
+        // First exception raised by func, to be reported once done
+        error: Mutex<Option<PyErr>>,
+    },
+}
+
+impl PyMetric {
+    /// Raise the first exception from a callable metric since the last call
+    pub fn take_error(&self) -> PyResult<()> {
+        match self {
+            Self::Callable { error, .. } => error.lock().unwrap().take().map_or(Ok(()), Err),
+            _ => Ok(()),
+        }
+    }
+}
+
+impl Clone for PyMetric {
+    fn clone(&self) -> Self {
+        match self {
+            Self::Levenshtein => Self::Levenshtein,
+            Self::Osa => Self::Osa,
+            Self::Hamming => Self::Hamming,
+            Self::Callable { func, .. } => Self::Callable {
+                func: func.clone(),
+                error: Mutex::new(None),
+            },
+        }
+    }
+}
+
+impl Metric for PyMetric {
+    fn distance(&self, a: &str, b: &str) -> u32 {
+        match self {
+            Self::Levenshtein => Levenshtein.distance(a, b),
+            Self::Osa => Osa.distance(a, b),
+            Self::Hamming => Hamming.distance(a, b),
+            Self::Callable { func, error } => {
+                if error.lock().unwrap().is_some() {
+                    return u32::MAX;
+                }
+                Python::with_gil(|py| func.call1(py, (a, b))?.extract(py)).unwrap_or_else(|e| {
+                    error.lock().unwrap().get_or_insert(e);
+                    u32::MAX
+                })
+            }
+        }
+    }
+
+    fn query<'a>(&'a self, query: &'a str) -> Box<dyn Fn(&str) -> u32 + 'a> {
+        match self {
+            Self::Levenshtein => Levenshtein.query(query),
+            Self::Osa => Osa.query(query),
+            _ => Box::new(move |other| self.distance(query, other)),
+        }
+    }
+
+    fn failed(&self) -> bool {
+        match self {
+            Self::Callable { error, .. } => error.lock().unwrap().is_some(),
+            _ => false,
+        }
+    }
+
+    fn builtin(&self) -> Option<BuiltinMetric> {
+        match self {
+            Self::Levenshtein => Some(BuiltinMetric::Levenshtein),
+            Self::Osa => Some(BuiltinMetric::Osa),
+            Self::Hamming => Some(BuiltinMetric::Hamming),
+            Self::Callable { .. } => None,
+        }
+    }
+}
+
+impl From<BuiltinMetric> for PyMetric {
+    fn from(metric: BuiltinMetric) -> Self {
+        match metric {
+            BuiltinMetric::Levenshtein => Self::Levenshtein,
+            BuiltinMetric::Osa => Self::Osa,
+            BuiltinMetric::Hamming => Self::Hamming,
+        }
+    }
+}
+
+impl<'source> FromPyObject<'source> for PyMetric {
+    fn extract(ob: &'source PyAny) -> PyResult<Self> {
+        if let Ok(name) = ob.extract::<&str>() {
+            return match name {
+                "levenshtein" => Ok(Self::Levenshtein),
+                "osa" | "damerau_levenshtein" => Ok(Self::Osa),
+                "hamming" => Ok(Self::Hamming),
+                other => Err(PyValueError::new_err(format!("unknown metric: {other}"))),
+            };
+        }
+        if ob.is_callable() {
+            return Ok(Self::Callable {
+                func: ob.into(),
+                error: Mutex::new(None),
+            });
+        }
+        Err(PyTypeError::new_err("metric must be a name or a callable"))
+    }
+}
+
+impl ToPyObject for PyMetric {
+    fn to_object(&self, py: Python<'_>) -> PyObject {
+        match self {
+            Self::Levenshtein => "levenshtein".to_object(py),
+            Self::Osa => "osa".to_object(py),
+            Self::Hamming => "hamming".to_object(py),
+            Self::Callable { func, .. } => func.clone_ref(py),
+        }
+    }
+}
+
+/// Automaton for a metric chosen from Python
+#[derive(Debug, Clone)]
+pub enum MetricAutomaton<'a> {
+    Levenshtein(LevenshteinAutomaton<'a>),
+    Osa(OsaAutomaton<'a>),
+    Weighted(WeightedAutomaton<'a>),
+}
+
+impl<'a> MetricAutomaton<'a> {
+    pub fn new(string: &'a str, metric: &PyMetric) -> PyResult<Self> {
+        match metric {
+            PyMetric::Levenshtein => Ok(Self::Levenshtein(LevenshteinAutomaton::new(string))),
+            PyMetric::Osa => Ok(Self::Osa(OsaAutomaton::new(string))),
+            _ => Err(PyValueError::new_err("metric has no automaton")),
+        }
+    }
+
+    /// Automaton for the metric, weighted unless weights are the default and
+    /// there are no substitutions
+    pub fn with_weights(
+        string: &'a str,
+        metric: &PyMetric,
+        weights: (u32, u32, u32),
+        substitutions: Option<&'a Substitutions>,
+    ) -> PyResult<Self> {
+        let (insert, delete, substitute) = weights;
+        let weights = Weights::new(insert, delete, substitute);
+        match (metric, substitutions) {
+            (_, None) if weights == Weights::default() => Self::new(string, metric),
+            (PyMetric::Levenshtein, None) => {
+                Ok(Self::Weighted(WeightedAutomaton::new(string, weights)))
+            }
+            (PyMetric::Levenshtein, Some(table)) => Ok(Self::Weighted(
+                WeightedAutomaton::with_substitutions(string, weights, table),
+            )),
+            _ => Err(PyValueError::new_err(
+                "weights and substitutions only apply to levenshtein",
+            )),
+        }
+    }
+
+    pub fn start(&self) -> MetricState<'_> {
+        match self {
+            Self::Levenshtein(m) => MetricState::Levenshtein(m.start()),
+            Self::Osa(m) => MetricState::Osa(m.start()),
+            Self::Weighted(m) => MetricState::Weighted(m.start()),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum MetricState<'a> {
+    Levenshtein(LevenshteinState<'a>),
+    Osa(OsaState<'a>),
+    Weighted(WeightedState<'a>),
+}
+
+impl AutomatonState for MetricState<'_> {
+    fn step_mut(&mut self, value: char) {
+        match self {
+            Self::Levenshtein(s) => s.step_mut(value),
+            Self::Osa(s) => s.step_mut(value),
+            Self::Weighted(s) => s.step_mut(value),
+        }
+    }
+
+    fn step(&self, value: char) -> Self {
+        match self {
+            Self::Levenshtein(s) => Self::Levenshtein(s.step(value)),
+            Self::Osa(s) => Self::Osa(s.step(value)),
+            Self::Weighted(s) => Self::Weighted(s.step(value)),
+        }
+    }
+
+    fn distance(&self) -> u32 {
+        match self {
+            Self::Levenshtein(s) => s.distance(),
+            Self::Osa(s) => s.distance(),
+            Self::Weighted(s) => s.distance(),
+        }
+    }
+
+    fn can_match(&self, max_edits: u32) -> bool {
+        match self {
+            Self::Levenshtein(s) => s.can_match(max_edits),
+            Self::Osa(s) => s.can_match(max_edits),
+            Self::Weighted(s) => s.can_match(max_edits),
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! Python bindings
+
+pub mod bktree;
+pub mod frozen;
+pub mod functions;
+pub mod metric;
+pub mod normalize;
+pub mod segmentation;
+pub mod trie;
+pub mod weighted;
+
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+use std::path::PathBuf;
+
+use crate::frozen::BuildError;
+use crate::segmentation::TooManyClusters;
+use crate::serialize::DecodeError;
+use crate::similarity::MinSimilarity;
+
+impl From<DecodeError> for PyErr {
+    fn from(err: DecodeError) -> Self {
+        PyValueError::new_err(err.to_string())
+    }
+}
+
+impl From<BuildError> for PyErr {
+    fn from(err: BuildError) -> Self {
+        PyValueError::new_err(err.to_string())
+    }
+}
+
+impl From<TooManyClusters> for PyErr {
+    fn from(err: TooManyClusters) -> Self {
+        PyValueError::new_err(err.to_string())
+    }
+}
+
+/// Key and payload pairs from a dict or an iterable of pairs
+fn extract_items(items: &PyAny) -> PyResult<Vec<(String, PyObject)>> {
+    if let Ok(dict) = items.downcast::<PyDict>() {
+        return dict
+            .iter()
+            .map(|(k, v)| Ok((k.extract()?, v.into())))
+            .collect();
+    }
+    items.iter()?.map(|item| item?.extract()).collect()
+}
+
+/// Save an index with payloads to a file by pickling it
+fn pickle_save(index: &PyAny, path: PathBuf) -> PyResult<()> {
+    let pickle = index.py().import("pickle")?;
+    let data: &PyBytes = pickle.call_method1("dumps", (index,))?.downcast()?;
+    Ok(std::fs::write(path, data.as_bytes())?)
+}
+
+/// Load an index with payloads pickled to a file
+fn pickle_load(py: Python<'_>, path: PathBuf) -> PyResult<&PyAny> {
+    let data = PyBytes::new(py, &std::fs::read(path)?);
+    py.import("pickle")?.call_method1("loads", (data,))
+}
+
+/// Check that min_similarity is between 0 and 1, as required by MinSimilarity
+fn check_similarity(min_similarity: Option<f64>) -> PyResult<()> {
+    match min_similarity {
+        Some(min) if !(0.0..=1.0).contains(&min) => Err(PyValueError::new_err(format!(
+            "min_similarity must be between 0 and 1: {min}"
+        ))),
+        _ => Ok(()),
+    }
+}
+
+/// Bound for a query of query_len from min_similarity if given, together with max_edits
+fn similarity_bound(
+    query_len: usize,
+    max_edits: Option<u32>,
+    min_similarity: Option<f64>,
+) -> PyResult<Option<MinSimilarity>> {
+    check_similarity(min_similarity)?;
+    let Some(min) = min_similarity else {
+        return Ok(None);
+    };
+    let bound = MinSimilarity::with_len(query_len, min);
+    Ok(Some(max_edits.map_or(bound, |x| bound.with_max_edits(x))))
+}
+
+/// Indices of chars at byte offsets into text, unless the same as offsets
+fn char_indices(text: &str) -> Option<Vec<usize>> {
+    if text.is_ascii() {
+        return None;
+    }
+    let mut index = vec![0; text.len() + 1];
+    for (i, (offset, _)) in text.char_indices().enumerate() {
+        index[offset] = i;
+    }
+    index[text.len()] = text.chars().count();
+    Some(index)
+}
+
+/// Strings from an iterable, stopping at the first error which is kept in error
+fn iter_strs<'py>(
+    items: &'py PyAny,
+    error: &'py mut Option<PyErr>,
+) -> PyResult<impl Iterator<Item = &'py str>> {
+    Ok(items.iter()?.map_while(|item| {
+        item.and_then(|x| x.extract())
+            .map_err(|e| *error = Some(e))
+            .ok()
+    }))
+}
@@ -0,0 +1,58 @@
+use pyo3::exceptions::{PyTypeError, PyValueError};
+use pyo3::prelude::*;
+
+use crate::normalize::{Normalization, Normalizer};
+
+fn normalization(name: &str) -> PyResult<Option<Normalization>> {
+    match name {
+        "none" => Ok(None),
+        "lowercase" => Ok(Some(Normalization::Lowercase)),
+        "casefold" => Ok(Some(Normalization::Casefold)),
+        "nfc" => Ok(Some(Normalization::Nfc)),
+        "nfkc" => Ok(Some(Normalization::Nfkc)),
+        "strip_accents" => Ok(Some(Normalization::StripAccents)),
+        other => Err(PyValueError::new_err(format!(
+            "unknown normalizer: {other}"
+        ))),
+    }
+}
+
+fn name(step: &Normalization) -> &'static str {
+    match step {
+        Normalization::Lowercase => "lowercase",
+        Normalization::Casefold => "casefold",
+        Normalization::Nfc => "nfc",
+        Normalization::Nfkc => "nfkc",
+        Normalization::StripAccents => "strip_accents",
+    }
+}
+
+impl<'source> FromPyObject<'source> for Normalizer {
+    fn extract(ob: &'source PyAny) -> PyResult<Self> {
+        if ob.is_none() {
+            return Ok(Self::default());
+        }
+        if let Ok(name) = ob.extract::<&str>() {
+            return Ok(Self::new(normalization(name)?));
+        }
+        let Ok(names) = ob.extract::<Vec<&str>>() else {
+            return Err(PyTypeError::new_err(
+                "normalizer must be a name or a sequence of names",
+            ));
+        };
+        let steps = names.into_iter().map(normalization);
+        Ok(Self::new(
+            steps.collect::<PyResult<Vec<_>>>()?.into_iter().flatten(),
+        ))
+    }
+}
+
+impl ToPyObject for Normalizer {
+    fn to_object(&self, py: Python<'_>) -> PyObject {
+        self.steps()
+            .iter()
+            .map(name)
+            .collect::<Vec<_>>()
+            .to_object(py)
+    }
+}
@@ -0,0 +1,25 @@
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+
+use crate::segmentation::Segmentation;
+
+impl<'source> FromPyObject<'source> for Segmentation {
+    fn extract(ob: &'source PyAny) -> PyResult<Self> {
+        match ob.extract::<&str>()? {
+            "codepoints" => Ok(Self::Codepoints),
+            "graphemes" => Ok(Self::Graphemes),
+            other => Err(PyValueError::new_err(format!(
+                "unknown segmentation: {other}"
+            ))),
+        }
+    }
+}
+
+impl ToPyObject for Segmentation {
+    fn to_object(&self, py: Python<'_>) -> PyObject {
+        match self {
+            Self::Codepoints => "codepoints".to_object(py),
+            Self::Graphemes => "graphemes".to_object(py),
+        }
+    }
+}
@@ -0,0 +1,569 @@
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyType};
+use rayon::prelude::*;
+use std::path::PathBuf;
+
+use super::extract_items;
+use super::functions::PyEditop;
+use super::metric::{MetricAutomaton, PyMetric};
+use super::weighted::PySubstitutions;
+use super::{char_indices, pickle_load, pickle_save, similarity_bound};
+use crate::normalize::Normalizer;
+use crate::segmentation::Segmentation;
+use crate::trie::{Selection, Trie, TrieMap};
+
+impl<'source> FromPyObject<'source> for Selection {
+    fn extract(ob: &'source PyAny) -> PyResult<Self> {
+        match ob.extract::<&str>()? {
+            "all" => Ok(Self::All),
+            "longest" => Ok(Self::Longest),
+            "non_overlapping" => Ok(Self::NonOverlapping),
+            other => Err(PyValueError::new_err(format!("unknown selection: {other}"))),
+        }
+    }
+}
+
+/// Trie storing the strings to search against
+///
+/// With segmentation "graphemes", edits are counted in grapheme clusters
+/// rather than codepoints. The normalizer, a name or a list of names applied
+/// in order, is applied to keys and queries, while keys are returned as
+/// inserted. Searches with the levenshtein metric take weights and
+/// substitutions as for the levenshtein function.
+#[pyclass(name = "Trie", module = "assrs")]
+#[derive(Debug, Default, Clone)]
+pub struct PyTrie(pub(crate) Trie);
+
+#[pymethods]
+impl PyTrie {
+    #[new]
+    #[pyo3(signature = (
+        items=None,
+        segmentation=Segmentation::Codepoints,
+        normalizer=Normalizer::default(),
+    ))]
+    fn py_new(
+        items: Option<Vec<String>>,
+        segmentation: Segmentation,
+        normalizer: Normalizer,
+    ) -> PyResult<Self> {
+        let mut trie = Trie::with_options(segmentation, normalizer);
+        for item in items.into_iter().flatten() {
+            trie.try_insert(item)?;
+        }
+        Ok(Self(trie))
+    }
+
+    #[staticmethod]
+    fn new() -> Self {
+        Self::default()
+    }
+
+    fn insert(&mut self, value: String) -> PyResult<()> {
+        Ok(self.0.try_insert(value)?)
+    }
+
+    fn remove(&mut self, value: &str) -> bool {
+        self.0.remove(value)
+    }
+
+    fn get(&self, value: &str) -> Option<&str> {
+        self.0.get(value)
+    }
+
+    fn contains(&self, value: &str) -> bool {
+        self.0.contains(value)
+    }
+
+    fn values(&self) -> Vec<&str> {
+        self.0.values()
+    }
+
+    /// Serialize the trie to bytes
+    fn to_bytes<'py>(&self, py: Python<'py>) -> &'py PyBytes {
+        PyBytes::new(py, &self.0.to_bytes())
+    }
+
+    /// Deserialize a trie from bytes, with the segmentation and normalizer stored
+    ///
+    /// Segmentation and normalizer, if given, must match those stored, and
+    /// are used for bytes from older versions which do not store them.
+    #[staticmethod]
+    #[pyo3(signature = (data, segmentation=None, normalizer=None))]
+    fn from_bytes(
+        data: &[u8],
+        segmentation: Option<Segmentation>,
+        normalizer: Option<Normalizer>,
+    ) -> PyResult<Self> {
+        Ok(Self(Trie::decode(data, segmentation, normalizer)?))
+    }
+
+    /// Save the trie to a file, with its segmentation and normalizer
+    fn save(&self, path: PathBuf) -> PyResult<()> {
+        Ok(std::fs::write(path, self.0.to_bytes())?)
+    }
+
+    /// Load a trie from a file, with the segmentation and normalizer stored
+    ///
+    /// Segmentation and normalizer, if given, are as for from_bytes.
+    #[staticmethod]
+    #[pyo3(signature = (path, segmentation=None, normalizer=None))]
+    fn load(
+        path: PathBuf,
+        segmentation: Option<Segmentation>,
+        normalizer: Option<Normalizer>,
+    ) -> PyResult<Self> {
+        Self::from_bytes(&std::fs::read(path)?, segmentation, normalizer)
+    }
+
+    fn __getstate__<'py>(&self, py: Python<'py>) -> &'py PyBytes {
+        self.to_bytes(py)
+    }
+
+    fn __setstate__(&mut self, state: &[u8]) -> PyResult<()> {
+        let normalizer = self.0.normalizer().clone();
+        self.0 = Trie::from_bytes_with_options(state, self.0.segmentation(), normalizer)?;
+        Ok(())
+    }
+
+    fn __reduce__<'py>(&self, py: Python<'py>) -> (&'py PyType, PyObject, &'py PyBytes) {
+        let args = (py.None(), self.0.segmentation(), self.0.normalizer());
+        (py.get_type::<Self>(), args.to_object(py), self.to_bytes(py))
+    }
+
+    /// Find best match in trie for query
+    ///
+    /// Matches can also be required to have at least min_similarity to query
+    #[pyo3(signature = (
+        query,
+        max_edits=None,
+        metric=PyMetric::Levenshtein,
+        min_similarity=None,
+        weights=(1, 1, 1),
+        substitutions=None,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn find_one(
+        &self,
+        py: Python<'_>,
+        query: &str,
+        max_edits: Option<u32>,
+        metric: PyMetric,
+        min_similarity: Option<f64>,
+        weights: (u32, u32, u32),
+        substitutions: Option<PyRef<PySubstitutions>>,
+    ) -> PyResult<Option<(&str, u32)>> {
+        let table = substitutions.as_deref().map(|x| &x.0);
+        let query = self.0.search_key(query);
+        let automaton = MetricAutomaton::with_weights(&query, &metric, weights, table)?;
+        let bound = similarity_bound(query.chars().count(), max_edits, min_similarity)?;
+        Ok(py.allow_threads(|| match bound {
+            Some(bound) => self.0.find_one_similar_with(&automaton.start(), &bound),
+            None => self.0.find_one_with(&automaton.start(), max_edits),
+        }))
+    }
+
+    /// Find best match in trie for query with the edits transforming query into it
+    ///
+    /// Returns (value, distance, editops) with editops as for the editops
+    /// function, positions counted after normalization and segmentation
+    #[pyo3(signature = (query, max_edits=None))]
+    fn find_one_with_ops(
+        &self,
+        py: Python<'_>,
+        query: &str,
+        max_edits: Option<u32>,
+    ) -> Option<(&str, u32, Vec<PyEditop>)> {
+        let (value, distance, ops) =
+            py.allow_threads(|| self.0.find_one_with_ops(query, max_edits))?;
+        let ops = ops.into_iter().map(|x| (x.kind.as_str(), x.src, x.dest));
+        Some((value, distance, ops.collect()))
+    }
+
+    /// Find best match in trie for each query, in parallel
+    #[pyo3(signature = (
+        queries,
+        max_edits=None,
+        metric=PyMetric::Levenshtein,
+        weights=(1, 1, 1),
+        substitutions=None,
+    ))]
+    fn find_one_batch(
+        &self,
+        py: Python<'_>,
+        queries: Vec<&str>,
+        max_edits: Option<u32>,
+        metric: PyMetric,
+        weights: (u32, u32, u32),
+        substitutions: Option<PyRef<PySubstitutions>>,
+    ) -> PyResult<Vec<Option<(&str, u32)>>> {
+        let table = substitutions.as_deref().map(|x| &x.0);
+        py.allow_threads(|| {
+            queries
+                .par_iter()
+                .map(|query| {
+                    let query = self.0.search_key(query);
+                    let automaton = MetricAutomaton::with_weights(&query, &metric, weights, table)?;
+                    Ok(self.0.find_one_with(&automaton.start(), max_edits))
+                })
+                .collect()
+        })
+    }
+
+    /// Find all matches in trie within max_edits of query
+    ///
+    /// Sorted by distance and then value
+    #[pyo3(signature = (
+        query,
+        max_edits,
+        metric=PyMetric::Levenshtein,
+        weights=(1, 1, 1),
+        substitutions=None,
+    ))]
+    fn find_all(
+        &self,
+        py: Python<'_>,
+        query: &str,
+        max_edits: u32,
+        metric: PyMetric,
+        weights: (u32, u32, u32),
+        substitutions: Option<PyRef<PySubstitutions>>,
+    ) -> PyResult<Vec<(&str, u32)>> {
+        let table = substitutions.as_deref().map(|x| &x.0);
+        let query = self.0.search_key(query);
+        let automaton = MetricAutomaton::with_weights(&query, &metric, weights, table)?;
+        Ok(py.allow_threads(|| self.0.find_all_with(&automaton.start(), max_edits)))
+    }
+
+    /// Find the k best matches in trie for query
+    ///
+    /// Sorted by distance and then value
+    #[pyo3(signature = (
+        query,
+        k,
+        max_edits=None,
+        metric=PyMetric::Levenshtein,
+        weights=(1, 1, 1),
+        substitutions=None,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn find_k(
+        &self,
+        py: Python<'_>,
+        query: &str,
+        k: usize,
+        max_edits: Option<u32>,
+        metric: PyMetric,
+        weights: (u32, u32, u32),
+        substitutions: Option<PyRef<PySubstitutions>>,
+    ) -> PyResult<Vec<(&str, u32)>> {
+        let table = substitutions.as_deref().map(|x| &x.0);
+        let query = self.0.search_key(query);
+        let automaton = MetricAutomaton::with_weights(&query, &metric, weights, table)?;
+        Ok(py.allow_threads(|| self.0.find_k_with(&automaton.start(), k, max_edits)))
+    }
+
+    /// All values starting with prefix
+    ///
+    /// Sorted by value
+    fn starts_with(&self, prefix: &str) -> Vec<&str> {
+        self.0.starts_with(prefix)
+    }
+
+    /// Find the best completions in trie with a prefix within max_edits of query
+    ///
+    /// Sorted by distance and then value
+    #[pyo3(signature = (
+        query,
+        max_edits,
+        limit=None,
+        metric=PyMetric::Levenshtein,
+        weights=(1, 1, 1),
+        substitutions=None,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn fuzzy_prefix(
+        &self,
+        py: Python<'_>,
+        query: &str,
+        max_edits: u32,
+        limit: Option<usize>,
+        metric: PyMetric,
+        weights: (u32, u32, u32),
+        substitutions: Option<PyRef<PySubstitutions>>,
+    ) -> PyResult<Vec<(&str, u32)>> {
+        let table = substitutions.as_deref().map(|x| &x.0);
+        let query = self.0.search_key(query);
+        let automaton = MetricAutomaton::with_weights(&query, &metric, weights, table)?;
+        Ok(py.allow_threads(|| {
+            self.0
+                .fuzzy_prefix_with(&automaton.start(), max_edits, limit)
+        }))
+    }
+
+    /// Find approximate occurrences of values in text within max_edits
+    ///
+    /// Returns a list of (start, end, value, distance) for spans text[start:end].
+    /// Each value is matched from each start to the longest span with its
+    /// lowest distance. Selection "all" keeps every span, sorted by start, end,
+    /// distance and then value, "longest" the longest span from each start, and
+    /// "non_overlapping" spans not overlapping each other, choosing the lowest
+    /// distance and then the longest spans first.
+    #[pyo3(signature = (text, max_edits, selection=Selection::All))]
+    fn scan(
+        &self,
+        py: Python<'_>,
+        text: &str,
+        max_edits: u32,
+        selection: Selection,
+    ) -> Vec<(usize, usize, &str, u32)> {
+        py.allow_threads(|| {
+            let spans = self.0.scan(text, max_edits, selection);
+            let Some(index) = char_indices(text) else {
+                return spans;
+            };
+            spans
+                .into_iter()
+                .map(|(start, end, value, distance)| (index[start], index[end], value, distance))
+                .collect()
+        })
+    }
+}
+
+/// Trie storing strings with an associated payload to search against
+///
+/// With segmentation "graphemes", edits are counted in grapheme clusters
+/// rather than codepoints. The normalizer, a name or a list of names applied
+/// in order, is applied to keys and queries, while keys are returned as
+/// inserted. Searches with the levenshtein metric take weights and
+/// substitutions as for the levenshtein function.
+#[pyclass(name = "TrieMap", module = "assrs")]
+#[derive(Debug, Default, Clone)]
+pub struct PyTrieMap(TrieMap<PyObject>);
+
+#[pymethods]
+impl PyTrieMap {
+    #[new]
+    #[pyo3(signature = (
+        items=None,
+        segmentation=Segmentation::Codepoints,
+        normalizer=Normalizer::default(),
+    ))]
+    fn py_new(
+        items: Option<&PyAny>,
+        segmentation: Segmentation,
+        normalizer: Normalizer,
+    ) -> PyResult<Self> {
+        let items = items.map(extract_items).transpose()?;
+        let mut trie = TrieMap::with_options(segmentation, normalizer);
+        for (key, value) in items.into_iter().flatten() {
+            trie.try_insert(key, value)?;
+        }
+        Ok(Self(trie))
+    }
+
+    #[staticmethod]
+    fn new() -> Self {
+        Self::default()
+    }
+
+    /// Insert key with payload, returning any previous payload
+    fn insert(&mut self, key: String, value: PyObject) -> PyResult<Option<PyObject>> {
+        Ok(self.0.try_insert(key, value)?)
+    }
+
+    /// Remove key, returning whether it was present
+    fn remove(&mut self, key: &str) -> bool {
+        self.0.remove(key).is_some()
+    }
+
+    /// Get (key, 0, payload) for key
+    fn get(&self, key: &str) -> Option<(&str, u32, &PyObject)> {
+        self.0.get(key).map(|(k, v)| (k, 0, v))
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        self.0.contains(key)
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.iter().map(|x| x.0).collect()
+    }
+
+    fn values(&self) -> Vec<&PyObject> {
+        self.0.iter().map(|x| x.1).collect()
+    }
+
+    fn items(&self) -> Vec<(&str, &PyObject)> {
+        self.0.iter().collect()
+    }
+
+    /// Save the trie to a file, pickling the payloads
+    fn save(slf: &PyCell<Self>, path: PathBuf) -> PyResult<()> {
+        pickle_save(slf, path)
+    }
+
+    /// Load a trie saved with save
+    ///
+    /// Loading unpickles the file, so only load files from trusted sources.
+    #[staticmethod]
+    fn load(py: Python<'_>, path: PathBuf) -> PyResult<Self> {
+        pickle_load(py, path)?.extract()
+    }
+
+    fn __reduce__<'py>(&self, py: Python<'py>) -> (&'py PyType, PyObject) {
+        let items: Vec<_> = self.0.iter().collect();
+        let args = (items, self.0.segmentation(), self.0.normalizer());
+        (py.get_type::<Self>(), args.to_object(py))
+    }
+
+    /// Find best match in trie for query
+    ///
+    /// Matches can also be required to have at least min_similarity to query
+    #[pyo3(signature = (
+        query,
+        max_edits=None,
+        metric=PyMetric::Levenshtein,
+        min_similarity=None,
+        weights=(1, 1, 1),
+        substitutions=None,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn find_one(
+        &self,
+        py: Python<'_>,
+        query: &str,
+        max_edits: Option<u32>,
+        metric: PyMetric,
+        min_similarity: Option<f64>,
+        weights: (u32, u32, u32),
+        substitutions: Option<PyRef<PySubstitutions>>,
+    ) -> PyResult<Option<(&str, u32, &PyObject)>> {
+        let table = substitutions.as_deref().map(|x| &x.0);
+        let query = self.0.search_key(query);
+        let automaton = MetricAutomaton::with_weights(&query, &metric, weights, table)?;
+        let bound = similarity_bound(query.chars().count(), max_edits, min_similarity)?;
+        Ok(py.allow_threads(|| match bound {
+            Some(bound) => self.0.find_one_similar_with(&automaton.start(), &bound),
+            None => self.0.find_one_with(&automaton.start(), max_edits),
+        }))
+    }
+
+    /// Find all matches in trie within max_edits of query
+    ///
+    /// Sorted by distance and then key
+    #[pyo3(signature = (
+        query,
+        max_edits,
+        metric=PyMetric::Levenshtein,
+        weights=(1, 1, 1),
+        substitutions=None,
+    ))]
+    fn find_all(
+        &self,
+        py: Python<'_>,
+        query: &str,
+        max_edits: u32,
+        metric: PyMetric,
+        weights: (u32, u32, u32),
+        substitutions: Option<PyRef<PySubstitutions>>,
+    ) -> PyResult<Vec<(&str, u32, &PyObject)>> {
+        let table = substitutions.as_deref().map(|x| &x.0);
+        let query = self.0.search_key(query);
+        let automaton = MetricAutomaton::with_weights(&query, &metric, weights, table)?;
+        Ok(py.allow_threads(|| self.0.find_all_with(&automaton.start(), max_edits)))
+    }
+
+    /// Find the k best matches in trie for query
+    ///
+    /// Sorted by distance and then key
+    #[pyo3(signature = (
+        query,
+        k,
+        max_edits=None,
+        metric=PyMetric::Levenshtein,
+        weights=(1, 1, 1),
+        substitutions=None,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn find_k(
+        &self,
+        py: Python<'_>,
+        query: &str,
+        k: usize,
+        max_edits: Option<u32>,
+        metric: PyMetric,
+        weights: (u32, u32, u32),
+        substitutions: Option<PyRef<PySubstitutions>>,
+    ) -> PyResult<Vec<(&str, u32, &PyObject)>> {
+        let table = substitutions.as_deref().map(|x| &x.0);
+        let query = self.0.search_key(query);
+        let automaton = MetricAutomaton::with_weights(&query, &metric, weights, table)?;
+        Ok(py.allow_threads(|| self.0.find_k_with(&automaton.start(), k, max_edits)))
+    }
+
+    /// All keys starting with prefix and their payloads
+    ///
+    /// Sorted by key
+    fn starts_with(&self, prefix: &str) -> Vec<(&str, &PyObject)> {
+        self.0.starts_with(prefix)
+    }
+
+    /// Find the best completions in trie with a prefix within max_edits of query
+    ///
+    /// Sorted by distance and then key
+    #[pyo3(signature = (
+        query,
+        max_edits,
+        limit=None,
+        metric=PyMetric::Levenshtein,
+        weights=(1, 1, 1),
+        substitutions=None,
+    ))]
+    #[allow(clippy::too_many_arguments)]
+    fn fuzzy_prefix(
+        &self,
+        py: Python<'_>,
+        query: &str,
+        max_edits: u32,
+        limit: Option<usize>,
+        metric: PyMetric,
+        weights: (u32, u32, u32),
+        substitutions: Option<PyRef<PySubstitutions>>,
+    ) -> PyResult<Vec<(&str, u32, &PyObject)>> {
+        let table = substitutions.as_deref().map(|x| &x.0);
+        let query = self.0.search_key(query);
+        let automaton = MetricAutomaton::with_weights(&query, &metric, weights, table)?;
+        Ok(py.allow_threads(|| {
+            self.0
+                .fuzzy_prefix_with(&automaton.start(), max_edits, limit)
+        }))
+    }
+
+    /// Find approximate occurrences of keys in text within max_edits
+    ///
+    /// Returns a list of (start, end, key, distance, payload) for spans
+    /// text[start:end], selected as for Trie.scan
+    #[pyo3(signature = (text, max_edits, selection=Selection::All))]
+    fn scan(
+        &self,
+        py: Python<'_>,
+        text: &str,
+        max_edits: u32,
+        selection: Selection,
+    ) -> Vec<(usize, usize, &str, u32, &PyObject)> {
+        py.allow_threads(|| {
+            let spans = self.0.scan(text, max_edits, selection);
+            let Some(index) = char_indices(text) else {
+                return spans;
+            };
+            spans
+                .into_iter()
+                .map(|(start, end, key, distance, value)| {
+                    (index[start], index[end], key, distance, value)
+                })
+                .collect()
+        })
+    }
+}
@@ -0,0 +1,53 @@
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::*;
+use std::collections::HashMap;
+
+use crate::weighted::Substitutions;
+
+/// Costs of substituting pairs of characters, with a default for other pairs
+///
+/// Pairs of longer strings, such as ("rn", "m"), are substituted as a whole
+#[pyclass(name = "Substitutions", module = "assrs")]
+#[derive(Debug, Clone)]
+pub struct PySubstitutions(pub(crate) Substitutions);
+
+#[pymethods]
+impl PySubstitutions {
+    #[new]
+    #[pyo3(signature = (default=1, costs=None))]
+    fn py_new(default: u32, costs: Option<HashMap<(&str, &str), u32>>) -> PyResult<Self> {
+        let mut table = Self(Substitutions::new(default));
+        for (pair, cost) in costs.into_iter().flatten() {
+            table.__setitem__(pair, cost)?;
+        }
+        Ok(table)
+    }
+
+    /// Table where substituting keys adjacent on a QWERTY keyboard costs adjacent
+    #[staticmethod]
+    #[pyo3(signature = (adjacent=1, default=2))]
+    fn qwerty(adjacent: u32, default: u32) -> Self {
+        Self(Substitutions::qwerty(adjacent, default))
+    }
+
+    /// Table where substituting characters commonly confused by OCR costs confusable
+    #[staticmethod]
+    #[pyo3(signature = (confusable=1, default=2))]
+    fn ocr(confusable: u32, default: u32) -> Self {
+        Self(Substitutions::ocr(confusable, default))
+    }
+
+    /// Cost of substituting a for b
+    fn __getitem__(&self, pair: (&str, &str)) -> u32 {
+        self.0.cost_str(pair.0, pair.1)
+    }
+
+    /// Set the cost of substituting a for b, in either direction
+    fn __setitem__(&mut self, pair: (&str, &str), cost: u32) -> PyResult<()> {
+        if pair.0.is_empty() || pair.1.is_empty() {
+            return Err(PyValueError::new_err("substitution of empty string"));
+        }
+        self.0.insert_str(pair.0, pair.1, cost);
+        Ok(())
+    }
+}
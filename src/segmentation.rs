@@ -0,0 +1,324 @@
+//! Segmentation of strings into extended grapheme clusters
+//!
+//! Distances count edits of chars, that is codepoints, so a letter with a
+//! combining accent or an emoji with modifiers counts as several. To compare
+//! extended grapheme clusters instead, strings are rewritten with each cluster
+//! as a single char and searched as usual.
+
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fmt;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::levenshtein::levenshtein;
+use crate::metric::{BuiltinMetric, Metric};
+
+/// Units that edits are counted in
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Segmentation {
+    /// Unicode scalar values, that is chars
+    #[default]
+    Codepoints,
+    /// Extended grapheme clusters
+    Graphemes,
+}
+
+impl Segmentation {
+    /// Code of the segmentation in serialized bytes
+    pub(crate) fn code(&self) -> u8 {
+        match self {
+            Self::Codepoints => 0,
+            Self::Graphemes => 1,
+        }
+    }
+
+    pub(crate) fn from_code(code: u8) -> Option<Self> {
+        match code {
+            0 => Some(Self::Codepoints),
+            1 => Some(Self::Graphemes),
+            _ => None,
+        }
+    }
+
+    /// Number of units in s
+    pub fn len(&self, s: &str) -> usize {
+        match self {
+            Self::Codepoints => s.chars().count(),
+            Self::Graphemes => s.graphemes(true).count(),
+        }
+    }
+}
+
+// Supplementary private use areas, planes 15 and 16
+const PLANE: u32 = 0xfffe;
+const FIRST: u32 = 0xf0000;
+
+fn private_use(index: u32) -> Option<char> {
+    match index {
+        i if i < PLANE => char::from_u32(FIRST + i),
+        i if i < 2 * PLANE => char::from_u32(FIRST + 0x10000 + i - PLANE),
+        _ => None,
+    }
+}
+
+fn is_private_use(c: char) -> bool {
+    matches!(c, '\u{f0000}'..='\u{ffffd}' | '\u{100000}'..='\u{10fffd}')
+}
+
+/// Error interning more distinct grapheme clusters than there are chars for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TooManyClusters;
+
+impl fmt::Display for TooManyClusters {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "too many distinct grapheme clusters")
+    }
+}
+
+impl std::error::Error for TooManyClusters {}
+
+/// Mapping of extended grapheme clusters to chars
+///
+/// Clusters of a single char are kept as they are, while others are assigned
+/// chars from the supplementary private use areas. Chars from those areas in
+/// the input are remapped too, so distinct clusters remain distinct. At most
+/// 131067 distinct clusters can be mapped.
+#[derive(Debug, Clone, Default)]
+pub struct Graphemes {
+    chars: HashMap<Box<str>, char>,
+}
+
+impl Graphemes {
+    // Stands in for every cluster not mapped, never assigned by intern
+    const UNKNOWN: char = '\u{f0000}';
+
+    /// Create an empty mapping
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn single(cluster: &str) -> Option<char> {
+        let mut chars = cluster.chars();
+        let c = chars.next()?;
+        (chars.next().is_none() && !is_private_use(c)).then_some(c)
+    }
+
+    /// Rewrite s with a char for each cluster, mapping new clusters
+    ///
+    /// Fails if no more chars are available for new clusters, leaving the
+    /// clusters of s mapped so far.
+    pub fn intern(&mut self, s: &str) -> Result<String, TooManyClusters> {
+        s.graphemes(true)
+            .map(|cluster| match Self::single(cluster) {
+                Some(c) => Ok(c),
+                None => match self.chars.get(cluster) {
+                    Some(&c) => Ok(c),
+                    None => {
+                        let c = private_use(self.chars.len() as u32 + 1).ok_or(TooManyClusters)?;
+                        self.chars.insert(cluster.into(), c);
+                        Ok(c)
+                    }
+                },
+            })
+            .collect()
+    }
+
+    /// Rewrite s with a char for each cluster, without mapping new clusters
+    ///
+    /// Clusters not mapped all become the same char, which is distinct from
+    /// any mapped cluster. The distance from a string rewritten this way to
+    /// an interned string is still the distance between the clusters.
+    pub fn lookup<'a>(&self, s: &'a str) -> Cow<'a, str> {
+        // Clusters of several ASCII chars are only ever CR LF
+        if s.bytes().all(|x| x.is_ascii() && x != b'\r') {
+            return Cow::Borrowed(s);
+        }
+        Cow::Owned(
+            s.graphemes(true)
+                .map(|cluster| match Self::single(cluster) {
+                    Some(c) => c,
+                    None => *self.chars.get(cluster).unwrap_or(&Self::UNKNOWN),
+                })
+                .collect(),
+        )
+    }
+}
+
+/// Find the Levenshtein distance between two strings in grapheme clusters
+///
+/// # Panics
+///
+/// Panics if a has more distinct clusters than [`Graphemes`] can map.
+pub fn levenshtein_graphemes(a: &str, b: &str) -> u32 {
+    let mut graphemes = Graphemes::new();
+    let a = graphemes
+        .intern(a)
+        .expect("too many distinct grapheme clusters");
+    levenshtein(&a, &graphemes.lookup(b))
+}
+
+/// Metric with edits counted in the units of a segmentation
+///
+/// With grapheme segmentation, distances panic if the first string or the
+/// query has more distinct clusters than [`Graphemes`] can map.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct Segmented<M> {
+    /// Metric of the strings of chars
+    pub metric: M,
+    /// Units that edits are counted in
+    pub segmentation: Segmentation,
+}
+
+impl<M> Segmented<M> {
+    /// Create the metric counting edits in units of segmentation
+    pub fn new(metric: M, segmentation: Segmentation) -> Self {
+        Self {
+            metric,
+            segmentation,
+        }
+    }
+}
+
+impl<M: Metric> Metric for Segmented<M> {
+    fn distance(&self, a: &str, b: &str) -> u32 {
+        match self.segmentation {
+            Segmentation::Codepoints => self.metric.distance(a, b),
+            Segmentation::Graphemes => {
+                let mut graphemes = Graphemes::new();
+                let a = graphemes
+                    .intern(a)
+                    .expect("too many distinct grapheme clusters");
+                self.metric.distance(&a, &graphemes.lookup(b))
+            }
+        }
+    }
+
+    fn query<'a>(&'a self, query: &'a str) -> Box<dyn Fn(&str) -> u32 + 'a> {
+        match self.segmentation {
+            Segmentation::Codepoints => self.metric.query(query),
+            Segmentation::Graphemes => {
+                let mut graphemes = Graphemes::new();
+                let query = graphemes
+                    .intern(query)
+                    .expect("too many distinct grapheme clusters");
+                Box::new(move |other| self.metric.distance(&query, &graphemes.lookup(other)))
+            }
+        }
+    }
+
+    fn len(&self, s: &str) -> usize {
+        self.segmentation.len(s)
+    }
+
+    fn failed(&self) -> bool {
+        self.metric.failed()
+    }
+
+    fn builtin(&self) -> Option<BuiltinMetric> {
+        self.metric.builtin()
+    }
+
+    fn segmentation(&self) -> Segmentation {
+        self.segmentation
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::levenshtein::{AutomatonState, LevenshteinAutomaton};
+    use crate::metric::{Hamming, Levenshtein, Osa};
+
+    #[test]
+    fn intern() {
+        let mut graphemes = Graphemes::new();
+        assert_eq!(graphemes.intern("foo").unwrap(), "foo");
+        assert_eq!(graphemes.intern("café").unwrap(), "café");
+        let accent = graphemes.intern("cafe\u{301}").unwrap();
+        assert_eq!(accent.chars().count(), 4);
+        assert_eq!(
+            graphemes.intern("e\u{301}e\u{301}").unwrap(),
+            accent[3..].repeat(2)
+        );
+        // Private use chars are remapped rather than colliding
+        let private = graphemes.intern("\u{f0001}").unwrap();
+        assert_ne!(private, accent[3..]);
+        assert_ne!(private, "\u{f0001}");
+        assert_eq!(graphemes.lookup("e\u{301}"), &accent[3..]);
+        assert_eq!(graphemes.lookup("a\u{301}"), "\u{f0000}");
+        assert_eq!(graphemes.lookup("a\u{301}o\u{301}"), "\u{f0000}\u{f0000}");
+        assert_eq!(private_use(PLANE), Some('\u{100000}'));
+        assert_eq!(private_use(2 * PLANE - 1), Some('\u{10fffd}'));
+        assert_eq!(private_use(2 * PLANE), None);
+    }
+
+    #[test]
+    fn too_many() {
+        let mut graphemes = Graphemes::new();
+        // Distinct clusters of a CJK ideograph with a combining mark
+        let mut clusters = ('\u{4e00}'..).flat_map(|c| {
+            ('\u{300}'..'\u{370}').map(move |m| [c, m].into_iter().collect::<String>())
+        });
+        for cluster in clusters.by_ref().take(2 * PLANE as usize - 1) {
+            graphemes.intern(&cluster).unwrap();
+        }
+        let cluster = clusters.next().unwrap();
+        assert_eq!(graphemes.intern(&cluster), Err(TooManyClusters));
+        assert_eq!(
+            graphemes.intern(&format!("foo{cluster}")),
+            Err(TooManyClusters)
+        );
+        // Clusters already mapped can still be interned
+        assert_eq!(
+            graphemes
+                .intern("foo\u{4e00}\u{300}")
+                .unwrap()
+                .chars()
+                .count(),
+            4
+        );
+        assert_eq!(graphemes.lookup(&cluster), "\u{f0000}");
+    }
+
+    #[test]
+    fn distances() {
+        assert_eq!(levenshtein("cafe\u{301}", "cafe"), 1);
+        assert_eq!(levenshtein("cafe\u{301}", "café"), 2);
+        assert_eq!(levenshtein_graphemes("cafe\u{301}", "cafe"), 1);
+        assert_eq!(levenshtein_graphemes("cafe\u{301}", "cafa\u{301}"), 1);
+        assert_eq!(
+            levenshtein_graphemes("a\u{301}o\u{301}", "o\u{301}a\u{301}"),
+            2
+        );
+        assert_eq!(levenshtein_graphemes("👍🏽", "👍"), 1);
+        assert_eq!(levenshtein_graphemes("👨‍👩‍👧", ""), 1);
+        assert_eq!(levenshtein_graphemes("foo", "bar"), 3);
+        assert_eq!(levenshtein_graphemes("a\r\n", "a\n"), 1);
+        assert_eq!(levenshtein_graphemes("a\n", "a\r\n"), 1);
+
+        let mut graphemes = Graphemes::new();
+        let query = graphemes.intern("ne\u{301}e").unwrap();
+        let automaton = LevenshteinAutomaton::new(&query);
+        assert_eq!(automaton.distance(&graphemes.lookup("nee")), 1);
+        assert_eq!(
+            automaton.distance(&graphemes.lookup("ne\u{301}e\u{301}")),
+            1
+        );
+        let mut state = automaton.start();
+        for c in graphemes.lookup("ne\u{301}").chars() {
+            state.step_mut(c);
+        }
+        assert_eq!(state.distance(), 1);
+
+        let metric = Segmented::new(Osa, Segmentation::Graphemes);
+        assert_eq!(metric.distance("e\u{301}a", "ae\u{301}"), 1);
+        assert_eq!(metric.query("e\u{301}a")("ae\u{301}"), 1);
+        let metric = Segmented::new(Hamming, Segmentation::Graphemes);
+        assert_eq!(metric.distance("e\u{301}a", "ea"), 1);
+        let metric = Segmented::new(Levenshtein, Segmentation::Codepoints);
+        assert_eq!(metric.distance("e\u{301}a", "ea"), 1);
+        assert_eq!(Segmentation::Graphemes.len("cafe\u{301}"), 4);
+        assert_eq!(Segmentation::Codepoints.len("cafe\u{301}"), 5);
+    }
+}
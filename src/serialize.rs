@@ -0,0 +1,195 @@
+//! Binary serialization of the search structures
+//!
+//! Serialized bytes start with a header of magic bytes, the kind of structure
+//! and the format version, followed by LEB128 integers and length-prefixed
+//! UTF-8 strings. Bytes of older versions are read as far as the structure
+//! has not changed since.
+
+use std::fmt;
+
+const MAGIC: &[u8; 5] = b"ASSRS";
+// Version 2 adds the options of tries, version 3 the metric of BK-trees
+const VERSION: u8 = 3;
+
+pub(crate) const TRIE: u8 = b'T';
+pub(crate) const BKTREE: u8 = b'B';
+pub(crate) const FROZEN: u8 = b'F';
+
+/// Length of the header in bytes
+pub(crate) const HEADER_LEN: usize = MAGIC.len() + 2;
+
+/// Error decoding serialized bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DecodeError {
+    /// Bytes do not start with the expected header
+    Header,
+    /// Format version is not supported
+    Version(u8),
+    /// Bytes end before the structure is complete
+    Truncated,
+    /// Bytes do not describe a valid structure
+    Invalid,
+    /// Options given differ from those stored in the bytes
+    Options,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Header => write!(f, "invalid header"),
+            Self::Version(v) => write!(f, "unsupported version: {v}"),
+            Self::Truncated => write!(f, "unexpected end of data"),
+            Self::Invalid => write!(f, "invalid data"),
+            Self::Options => write!(f, "options differ from those stored"),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {}
+
+pub(crate) struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new(kind: u8) -> Self {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend([kind, VERSION]);
+        Self { bytes }
+    }
+
+    pub fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    pub fn varint(&mut self, mut value: u64) {
+        while value >= 0x80 {
+            self.bytes.push(value as u8 | 0x80);
+            value >>= 7;
+        }
+        self.bytes.push(value as u8);
+    }
+
+    pub fn str(&mut self, value: &str) {
+        self.varint(value.len() as u64);
+        self.bytes.extend(value.as_bytes());
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+    version: u8,
+}
+
+impl<'a> Reader<'a> {
+    pub fn new(bytes: &'a [u8], kind: u8) -> Result<Self, DecodeError> {
+        let header = bytes.get(..HEADER_LEN).ok_or(DecodeError::Header)?;
+        if &header[..MAGIC.len()] != MAGIC || header[MAGIC.len()] != kind {
+            return Err(DecodeError::Header);
+        }
+        match header[MAGIC.len() + 1] {
+            version @ 1..=VERSION => Ok(Self {
+                bytes: &bytes[header.len()..],
+                version,
+            }),
+            v => Err(DecodeError::Version(v)),
+        }
+    }
+
+    /// Format version of the bytes
+    pub fn version(&self) -> u8 {
+        self.version
+    }
+
+    pub fn u8(&mut self) -> Result<u8, DecodeError> {
+        let (&value, rest) = self.bytes.split_first().ok_or(DecodeError::Truncated)?;
+        self.bytes = rest;
+        Ok(value)
+    }
+
+    pub fn varint(&mut self) -> Result<u64, DecodeError> {
+        let mut value = 0;
+        for shift in (0..64).step_by(7) {
+            let byte = self.u8()?;
+            value |= ((byte & 0x7f) as u64) << shift;
+            if byte < 0x80 {
+                return Ok(value);
+            }
+        }
+        Err(DecodeError::Invalid)
+    }
+
+    pub fn usize(&mut self) -> Result<usize, DecodeError> {
+        self.varint()?.try_into().map_err(|_| DecodeError::Invalid)
+    }
+
+    pub fn str(&mut self) -> Result<&'a str, DecodeError> {
+        let len = self.usize()?;
+        if len > self.bytes.len() {
+            return Err(DecodeError::Truncated);
+        }
+        let (value, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        std::str::from_utf8(value).map_err(|_| DecodeError::Invalid)
+    }
+
+    /// Check that all bytes have been read
+    pub fn finish(self) -> Result<(), DecodeError> {
+        if self.bytes.is_empty() {
+            Ok(())
+        } else {
+            Err(DecodeError::Invalid)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn roundtrip() {
+        let mut writer = Writer::new(TRIE);
+        writer.varint(0);
+        writer.varint(300);
+        writer.varint(u64::MAX);
+        writer.str("föo");
+        writer.u8(7);
+        let bytes = writer.into_bytes();
+
+        let mut reader = Reader::new(&bytes, TRIE).unwrap();
+        assert_eq!(reader.varint(), Ok(0));
+        assert_eq!(reader.varint(), Ok(300));
+        assert_eq!(reader.varint(), Ok(u64::MAX));
+        assert_eq!(reader.str(), Ok("föo"));
+        assert_eq!(reader.u8(), Ok(7));
+        assert_eq!(reader.u8(), Err(DecodeError::Truncated));
+        assert_eq!(reader.finish(), Ok(()));
+    }
+
+    #[test]
+    fn header() {
+        let bytes = Writer::new(TRIE).into_bytes();
+        assert!(Reader::new(&bytes, TRIE).is_ok());
+        assert!(matches!(
+            Reader::new(&bytes, BKTREE),
+            Err(DecodeError::Header)
+        ));
+        assert!(matches!(
+            Reader::new(&bytes[..3], TRIE),
+            Err(DecodeError::Header)
+        ));
+        let mut bytes = bytes;
+        *bytes.last_mut().unwrap() = 1;
+        assert_eq!(Reader::new(&bytes, TRIE).unwrap().version(), 1);
+        *bytes.last_mut().unwrap() = 99;
+        assert!(matches!(
+            Reader::new(&bytes, TRIE),
+            Err(DecodeError::Version(99))
+        ));
+    }
+}
@@ -0,0 +1,151 @@
+//! Normalized similarity and bounds on distance relative to length
+
+use crate::levenshtein::levenshtein;
+use crate::osa::osa;
+
+/// Normalized similarity of strings with lengths len_a and len_b at distance
+///
+/// Defined as `1 - distance / max(len_a, len_b)`, and 1 for two empty strings.
+pub fn similarity(distance: u32, len_a: usize, len_b: usize) -> f64 {
+    match len_a.max(len_b) {
+        0 => 1.0,
+        len => 1.0 - distance as f64 / len as f64,
+    }
+}
+
+/// Normalized Levenshtein similarity between two strings
+pub fn levenshtein_similarity(a: &str, b: &str) -> f64 {
+    similarity(levenshtein(a, b), a.chars().count(), b.chars().count())
+}
+
+/// Normalized optimal string alignment similarity between two strings
+pub fn osa_similarity(a: &str, b: &str) -> f64 {
+    similarity(osa(a, b), a.chars().count(), b.chars().count())
+}
+
+/// Bound on the distance from a query given by a minimum similarity
+///
+/// The number of edits allowed depends on the length of the candidate, so
+/// searches prune with the largest bound for any length and check each
+/// candidate found.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MinSimilarity {
+    min: f64,
+    query_len: usize,
+    max_edits: u32,
+}
+
+impl MinSimilarity {
+    /// Create the bound for a query with at least min similarity
+    ///
+    /// # Panics
+    ///
+    /// Panics if min is not between 0 and 1, or is NaN.
+    pub fn new(query: &str, min: f64) -> Self {
+        Self::with_len(query.chars().count(), min)
+    }
+
+    /// Create the bound for a query of query_len units with at least min similarity
+    ///
+    /// # Panics
+    ///
+    /// Panics if min is not between 0 and 1, or is NaN.
+    pub fn with_len(query_len: usize, min: f64) -> Self {
+        assert!(
+            (0.0..=1.0).contains(&min),
+            "min similarity not between 0 and 1: {min}"
+        );
+        Self {
+            min,
+            query_len,
+            max_edits: u32::MAX,
+        }
+    }
+
+    /// Also bound the distance by max_edits
+    pub fn with_max_edits(self, max_edits: u32) -> Self {
+        Self { max_edits, ..self }
+    }
+
+    /// Maximum distance for a candidate with len characters, if any
+    pub fn max_edits(&self, len: usize) -> Option<u32> {
+        let longest = self.query_len.max(len);
+        let max = u32::try_from(longest).unwrap_or(u32::MAX);
+        let mut edits = ((1.0 - self.min) * longest as f64).clamp(0.0, max as f64) as u32;
+        // Agree with similarity exactly despite rounding
+        while edits < max && similarity(edits + 1, self.query_len, len) >= self.min {
+            edits += 1;
+        }
+        while similarity(edits, self.query_len, len) < self.min {
+            edits = edits.checked_sub(1)?;
+        }
+        Some(edits.min(self.max_edits))
+    }
+
+    /// Maximum distance for a candidate of any length, if any
+    pub fn bound(&self) -> Option<u32> {
+        if self.min <= 0.0 {
+            return Some(self.max_edits);
+        }
+        // Distance is at least the difference in length, so longer
+        // candidates cannot be similar enough
+        let longest = (self.query_len as f64 / self.min).min(u32::MAX as f64) as usize;
+        self.max_edits(longest.saturating_add(1).max(self.query_len))
+    }
+
+    /// Whether a candidate with len characters at distance is similar enough
+    pub fn accepts(&self, distance: u32, len: usize) -> bool {
+        self.max_edits(len).is_some_and(|x| distance <= x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn similarities() {
+        assert_eq!(levenshtein_similarity("", ""), 1.0);
+        assert_eq!(levenshtein_similarity("foo", ""), 0.0);
+        assert_eq!(levenshtein_similarity("foo", "foo"), 1.0);
+        assert_eq!(levenshtein_similarity("kitten", "sitting"), 1.0 - 3.0 / 7.0);
+        assert_eq!(levenshtein_similarity("fö", "fo"), 0.5);
+        assert_eq!(osa_similarity("teh", "the"), 1.0 - 1.0 / 3.0);
+    }
+
+    #[test]
+    fn bounds() {
+        let bound = MinSimilarity::new("kitten", 0.8);
+        assert_eq!(bound.max_edits(0), Some(1));
+        assert_eq!(bound.max_edits(5), Some(1));
+        assert_eq!(bound.max_edits(10), Some(2));
+        assert!(bound.accepts(1, 5));
+        assert!(!bound.accepts(2, 5));
+        // Candidates with more than 7 characters need too many edits
+        assert_eq!(bound.bound(), Some(1));
+        assert_eq!(bound.with_max_edits(1).max_edits(10), Some(1));
+
+        for min in [0.0, 0.3, 0.5, 0.75, 0.8, 0.9, 1.0] {
+            let bound = MinSimilarity::new("foobar", min);
+            for len in 0..20 {
+                for distance in 0..20 {
+                    assert_eq!(
+                        bound.accepts(distance, len),
+                        distance as usize <= len.max(6) && similarity(distance, 6, len) >= min
+                    );
+                }
+            }
+        }
+
+        assert_eq!(MinSimilarity::new("", 1.0).max_edits(0), Some(0));
+        assert_eq!(MinSimilarity::new("foo", 0.0).bound(), Some(u32::MAX));
+    }
+
+    #[test]
+    fn invalid() {
+        for min in [-0.1, 1.5, f64::NAN, f64::INFINITY] {
+            let result = std::panic::catch_unwind(|| MinSimilarity::new("foo", min));
+            assert!(result.is_err());
+        }
+    }
+}
@@ -1,64 +1,1034 @@
-use pyo3::prelude::*;
-use std::collections::HashMap;
+//! Trie for searching with automata
 
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::editops::{editops, Editop};
+use crate::heap::{BoundedHeap, Keyed};
 use crate::levenshtein::{AutomatonState, LevenshteinAutomaton};
+use crate::normalize::{Normalization, Normalizer};
+use crate::segmentation::{Graphemes, Segmentation, TooManyClusters};
+use crate::serialize::{self, DecodeError, Reader, Writer};
+use crate::similarity::MinSimilarity;
 
-/// Trie storing the strings to search against
-#[pyclass]
-#[derive(Debug, Default, Clone)]
-pub struct Trie {
+/// Trie storing strings with an associated value to search against
+///
+/// Searches traverse the trie with an automaton, skipping subtries that cannot
+/// contain a sufficiently good match. Chains of nodes with a single child are
+/// compressed into edges labelled with the whole segment.
+///
+/// Keys and queries are normalized by the normalizer before searching, while
+/// keys are returned as inserted.
+#[derive(Debug, Clone)]
+pub struct TrieMap<V> {
+    root: Node<V>,
+    normalizer: Normalizer,
+    // Edge labels have a char for each grapheme cluster if present
+    graphemes: Option<Graphemes>,
+}
+
+/// Spans kept by [`TrieMap::scan`]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Selection {
+    /// Every span, sorted by start, end, distance and then key
+    #[default]
+    All,
+    /// The longest span from each start, then with the lowest distance, sorted
+    /// by start
+    Longest,
+    /// Spans not overlapping each other, choosing the lowest distance and then
+    /// the longest spans first, sorted by start
+    NonOverlapping,
+}
+
+#[derive(Debug, Clone)]
+struct Node<V> {
     // Indicates terminal and nice when traversing
-    value: Option<String>,
-    // Expensive to iterate over HashMap as O(capacity) rather than O(len)
-    children_index: HashMap<char, usize>,
-    children: Vec<(char, Trie)>,
+    value: Option<(String, V)>,
+    // Non-empty segments starting with distinct chars, in insertion order
+    children: Vec<(Box<str>, Node<V>)>,
 }
 
-#[pymethods]
-impl Trie {
-    #[new]
-    pub fn py_new(items: Option<Vec<String>>) -> Self {
-        items.map_or_else(Self::new, Self::from_iter)
+impl<V> Default for TrieMap<V> {
+    fn default() -> Self {
+        Self::with_options(Segmentation::Codepoints, Normalizer::default())
+    }
+}
+
+impl<V> Default for Node<V> {
+    fn default() -> Self {
+        Self {
+            value: None,
+            children: Vec::new(),
+        }
+    }
+}
+
+/// Length in bytes of the common prefix of a and b
+fn common_prefix(a: &str, b: &str) -> usize {
+    a.chars()
+        .zip(b.chars())
+        .take_while(|(x, y)| x == y)
+        .map(|(x, _)| x.len_utf8())
+        .sum()
+}
+
+/// Step state over the segment, or None once it can no longer match
+fn step_segment<S: AutomatonState>(state: &S, segment: &str, max_edits: u32) -> Option<S> {
+    let mut chars = segment.chars();
+    let mut state = state.step(chars.next()?);
+    for c in chars {
+        if !state.can_match(max_edits) {
+            return None;
+        }
+        state.step_mut(c);
+    }
+    Some(state)
+}
+
+/// Step state over the segment, keeping track of the lowest distance of the
+/// prefixes ending within it, or None once no prefix can be within max_edits
+fn step_prefix_segment<S: AutomatonState>(
+    state: &S,
+    segment: &str,
+    best: u32,
+    max_edits: u32,
+) -> Option<(S, u32)> {
+    let mut chars = segment.chars();
+    let mut state = state.step(chars.next()?);
+    let mut best = best;
+    for c in chars {
+        best = best.min(state.distance());
+        if best > max_edits && !state.can_match(max_edits) {
+            return None;
+        }
+        state.step_mut(c);
     }
+    Some((state, best))
+}
 
-    #[staticmethod]
+impl<V> TrieMap<V> {
+    /// Create an empty trie
     pub fn new() -> Self {
         Self::default()
     }
 
-    pub fn insert(&mut self, value: String) {
+    /// Create an empty trie counting edits in units of segmentation
+    pub fn with_segmentation(segmentation: Segmentation) -> Self {
+        Self::with_options(segmentation, Normalizer::default())
+    }
+
+    /// Create an empty trie normalizing keys and queries with normalizer
+    pub fn with_normalizer(normalizer: Normalizer) -> Self {
+        Self::with_options(Segmentation::Codepoints, normalizer)
+    }
+
+    /// Create an empty trie with both segmentation and normalizer
+    pub fn with_options(segmentation: Segmentation, normalizer: Normalizer) -> Self {
+        Self {
+            root: Node::default(),
+            normalizer,
+            graphemes: match segmentation {
+                Segmentation::Codepoints => None,
+                Segmentation::Graphemes => Some(Graphemes::new()),
+            },
+        }
+    }
+
+    /// Units that edits are counted in
+    pub fn segmentation(&self) -> Segmentation {
+        match self.graphemes {
+            Some(_) => Segmentation::Graphemes,
+            None => Segmentation::Codepoints,
+        }
+    }
+
+    /// Normalizer applied to keys and queries
+    pub fn normalizer(&self) -> &Normalizer {
+        &self.normalizer
+    }
+
+    /// String as read by searches, to build automata for the `_with` methods
+    ///
+    /// The string is normalized, and with grapheme segmentation each cluster
+    /// is then rewritten as a single char, see [`Graphemes::lookup`].
+    pub fn search_key<'a>(&self, s: &'a str) -> Cow<'a, str> {
+        match (self.normalizer.normalize(s), &self.graphemes) {
+            (s, None) => s,
+            (Cow::Borrowed(s), Some(graphemes)) => graphemes.lookup(s),
+            (Cow::Owned(s), Some(graphemes)) => Cow::Owned(graphemes.lookup(&s).into_owned()),
+        }
+    }
+
+    /// Insert key with value into the trie, returning any previous value
+    ///
+    /// A key normalized the same as a stored key replaces it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the trie has grapheme segmentation and no more chars are
+    /// available for new clusters, see [`try_insert`](Self::try_insert).
+    pub fn insert(&mut self, key: String, value: V) -> Option<V> {
+        self.try_insert(key, value)
+            .expect("too many distinct grapheme clusters")
+    }
+
+    /// Insert key with value into the trie, returning any previous value
+    ///
+    /// Fails without inserting if the trie has grapheme segmentation and no
+    /// more chars are available for new clusters.
+    pub fn try_insert(&mut self, key: String, value: V) -> Result<Option<V>, TooManyClusters> {
+        let normalized = self.normalizer.normalize(&key);
+        let node = match &mut self.graphemes {
+            Some(graphemes) => self.root.node_mut(&graphemes.intern(&normalized)?),
+            None => self.root.node_mut(&normalized),
+        };
+        Ok(node.value.replace((key, value)).map(|x| x.1))
+    }
+
+    /// Remove key from the trie, returning its value if it was present
+    pub fn remove(&mut self, key: &str) -> Option<V> {
+        let path = self.search_key(key).into_owned();
+        self.root.remove(&path)
+    }
+
+    /// Get the stored key normalized the same as key and its value
+    pub fn get(&self, key: &str) -> Option<(&str, &V)> {
+        let node = self.root.node(&self.search_key(key))?;
+        node.value.as_ref().map(|(k, v)| (k.as_str(), v))
+    }
+
+    /// Whether the trie contains key
+    pub fn contains(&self, key: &str) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Find best match in trie for query
+    pub fn find_one(&self, query: &str, max_edits: Option<u32>) -> Option<(&str, u32, &V)> {
+        let query = self.search_key(query);
+        let automaton = LevenshteinAutomaton::new(&query);
+        self.find_one_with(&automaton.start(), max_edits)
+    }
+
+    /// Find best match in trie for the query of an automaton
+    pub fn find_one_with(
+        &self,
+        start: &impl AutomatonState,
+        max_edits: Option<u32>,
+    ) -> Option<(&str, u32, &V)> {
+        let max_edits = max_edits.unwrap_or(u32::MAX);
+        self.root.find_automaton(start, max_edits, &|_, _| true)
+    }
+
+    /// Find best match in trie for query with the edits transforming query into it
+    ///
+    /// Positions of the edits are chars of the query and key as searched,
+    /// see [`TrieMap::search_key`].
+    pub fn find_one_with_ops(
+        &self,
+        query: &str,
+        max_edits: Option<u32>,
+    ) -> Option<(&str, u32, &V, Vec<Editop>)> {
+        let (key, distance, value) = self.find_one(query, max_edits)?;
+        let ops = editops(&self.search_key(query), &self.search_key(key));
+        Some((key, distance, value, ops))
+    }
+
+    /// Find best match in trie with at least min_similarity to query
+    ///
+    /// Similarity is normalized by the length of the longer string, see
+    /// [`MinSimilarity`].
+    pub fn find_one_similar(&self, query: &str, min_similarity: f64) -> Option<(&str, u32, &V)> {
+        let query = self.search_key(query);
+        let automaton = LevenshteinAutomaton::new(&query);
+        let bound = MinSimilarity::new(&query, min_similarity);
+        self.find_one_similar_with(&automaton.start(), &bound)
+    }
+
+    /// Find best match in trie within the bound for the query of an automaton
+    pub fn find_one_similar_with(
+        &self,
+        start: &impl AutomatonState,
+        bound: &MinSimilarity,
+    ) -> Option<(&str, u32, &V)> {
+        self.root
+            .find_automaton(start, bound.bound()?, &|key, distance| {
+                bound.accepts(distance, self.search_key(key).chars().count())
+            })
+    }
+
+    /// Find all matches in trie within max_edits of query
+    ///
+    /// Sorted by distance and then key
+    pub fn find_all(&self, query: &str, max_edits: u32) -> Vec<(&str, u32, &V)> {
+        let query = self.search_key(query);
+        let automaton = LevenshteinAutomaton::new(&query);
+        self.find_all_with(&automaton.start(), max_edits)
+    }
+
+    /// Find all matches in trie within max_edits of the query of an automaton
+    pub fn find_all_with(
+        &self,
+        start: &impl AutomatonState,
+        max_edits: u32,
+    ) -> Vec<(&str, u32, &V)> {
+        let mut result = Vec::new();
+        self.root.find_all_automaton(start, max_edits, &mut result);
+        result.sort_unstable_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(b.0)));
+        result
+    }
+
+    /// Find the k best matches in trie for query
+    ///
+    /// Sorted by distance and then key
+    pub fn find_k(&self, query: &str, k: usize, max_edits: Option<u32>) -> Vec<(&str, u32, &V)> {
+        let query = self.search_key(query);
+        let automaton = LevenshteinAutomaton::new(&query);
+        self.find_k_with(&automaton.start(), k, max_edits)
+    }
+
+    /// Find the k best matches in trie for the query of an automaton
+    pub fn find_k_with(
+        &self,
+        start: &impl AutomatonState,
+        k: usize,
+        max_edits: Option<u32>,
+    ) -> Vec<(&str, u32, &V)> {
+        // Nothing would be pruned with an empty heap
+        if k == 0 {
+            return Vec::new();
+        }
+        let mut heap = BoundedHeap::new(k);
+        self.root
+            .find_k_automaton(start, max_edits.unwrap_or(u32::MAX), &mut heap);
+        heap.into_sorted_vec()
+            .into_iter()
+            .map(|Keyed((distance, key), value)| (key, distance, value))
+            .collect()
+    }
+
+    /// Find approximate occurrences of keys in text within max_edits
+    ///
+    /// Returns (start, end, key, distance, value) for spans of text, with
+    /// start and end byte offsets. Each key is matched from each start to the
+    /// longest span with its lowest distance, and selection picks among the
+    /// overlapping spans. Spans start and end between grapheme clusters if the
+    /// trie has grapheme segmentation or a normalizer, which is applied to
+    /// each cluster separately.
+    pub fn scan(
+        &self,
+        text: &str,
+        max_edits: u32,
+        selection: Selection,
+    ) -> Vec<(usize, usize, &str, u32, &V)> {
+        // Text as searched, with the span of text each char comes from
+        let units: Box<dyn Iterator<Item = (usize, &str)>> =
+            if self.graphemes.is_none() && self.normalizer.is_none() {
+                Box::new(
+                    text.char_indices()
+                        .map(|(i, c)| (i, &text[i..i + c.len_utf8()])),
+                )
+            } else {
+                Box::new(text.grapheme_indices(true))
+            };
+        let mut search = String::new();
+        let mut offsets = Vec::new();
+        let mut bounds = Vec::new();
+        for (i, unit) in units {
+            for c in self.search_key(unit).chars() {
+                offsets.push(search.len());
+                bounds.push((i, i + unit.len()));
+                search.push(c);
+            }
+        }
+        offsets.push(search.len());
+
+        // Keys within max_edits of some substring, in a single walk
+        let automaton = LevenshteinAutomaton::new(&search);
+        let mut candidates = Vec::new();
+        self.root
+            .scan_automaton(&automaton.start_anywhere(), max_edits, &mut candidates);
+
+        let chars: Vec<char> = search.chars().collect();
+        let mut spans = Vec::new();
+        for (key, value, state) in candidates {
+            let key_search = self.search_key(key);
+            // An empty key does not occur anywhere in particular
+            if key_search.is_empty() {
+                continue;
+            }
+            // Spans within max_edits are no longer than the key with an
+            // insertion for each edit
+            let window = key_search
+                .chars()
+                .count()
+                .saturating_add(max_edits as usize);
+            let reversed: String = key_search.chars().rev().collect();
+            let backward = LevenshteinAutomaton::new(&reversed);
+            let forward = LevenshteinAutomaton::new(&key_search);
+            // Where spans of the key within max_edits end, latest first
+            let mut ends = state
+                .prefixes_within(max_edits)
+                .into_iter()
+                .rev()
+                .peekable();
+            while let Some(top) = ends.next() {
+                // Ends sharing starts within a window are read in one pass
+                let mut low = top;
+                while let Some(end) = ends.next_if(|x| x.saturating_add(window) >= low) {
+                    low = end;
+                }
+                // Reading backwards from top, the lowest distance from each
+                // start to a span ending at most at top
+                let mut state = backward.start_free();
+                for start in (low.saturating_sub(window)..top).rev() {
+                    state.step_mut(chars[start]);
+                    let lowest = state.distance();
+                    if lowest > max_edits || start > 0 && bounds[start - 1] == bounds[start] {
+                        continue;
+                    }
+                    // The longest span with the lowest distance
+                    let mut state = forward.start();
+                    let mut len = 0;
+                    for (i, &c) in chars[start..].iter().enumerate() {
+                        state.step_mut(c);
+                        if state.distance() == lowest {
+                            len = i + 1;
+                        }
+                        if !state.can_match(lowest) {
+                            break;
+                        }
+                    }
+                    let span = (bounds[start].0, bounds[start + len - 1].1);
+                    spans.push((span, len, key, lowest, value));
+                }
+            }
+        }
+
+        match selection {
+            Selection::All => {
+                spans.sort_unstable_by_key(|x| (x.0, x.3, x.2));
+            }
+            Selection::Longest => {
+                spans.sort_unstable_by_key(|x| (x.0 .0, Reverse(x.1), x.3, x.2));
+                spans.dedup_by_key(|x| x.0 .0);
+            }
+            Selection::NonOverlapping => {
+                spans.sort_unstable_by_key(|x| (x.3, Reverse(x.1), x.0, x.2));
+                // Chosen spans by start, with their ends
+                let mut chosen = BTreeMap::new();
+                spans.retain(|((start, end), ..)| {
+                    let overlaps = chosen
+                        .range(..*end)
+                        .next_back()
+                        .is_some_and(|(_, x)| x > start);
+                    if !overlaps {
+                        chosen.insert(*start, *end);
+                    }
+                    !overlaps
+                });
+                spans.sort_unstable_by_key(|x| x.0);
+            }
+        }
+        spans
+            .into_iter()
+            .map(|((start, end), _, key, distance, value)| (start, end, key, distance, value))
+            .collect()
+    }
+
+    /// Iterate over all keys and values in the trie
+    pub fn iter<'a>(&'a self) -> Box<dyn Iterator<Item = (&'a str, &'a V)> + 'a> {
+        self.root.iter()
+    }
+
+    /// All keys starting with prefix and their values
+    ///
+    /// Sorted by key
+    pub fn starts_with(&self, prefix: &str) -> Vec<(&str, &V)> {
+        let subtrie = self.root.subtrie(&self.search_key(prefix));
+        let mut result: Vec<_> = subtrie.into_iter().flat_map(|x| x.iter()).collect();
+        result.sort_unstable_by_key(|x| x.0);
+        result
+    }
+
+    /// Find the best completions in trie with a prefix within max_edits of query
+    ///
+    /// The distance of a key is the lowest distance of any of its prefixes.
+    /// Sorted by distance and then key, with at most limit results.
+    pub fn fuzzy_prefix(
+        &self,
+        query: &str,
+        max_edits: u32,
+        limit: Option<usize>,
+    ) -> Vec<(&str, u32, &V)> {
+        let query = self.search_key(query);
+        let automaton = LevenshteinAutomaton::new(&query);
+        self.fuzzy_prefix_with(&automaton.start(), max_edits, limit)
+    }
+
+    /// Find the best completions in trie for the query of an automaton
+    pub fn fuzzy_prefix_with(
+        &self,
+        start: &impl AutomatonState,
+        max_edits: u32,
+        limit: Option<usize>,
+    ) -> Vec<(&str, u32, &V)> {
+        let mut heap = BoundedHeap::new(limit.unwrap_or(usize::MAX));
+        self.root
+            .fuzzy_prefix_automaton(start, u32::MAX, max_edits, &mut heap);
+        heap.into_sorted_vec()
+            .into_iter()
+            .map(|Keyed((distance, key), value)| (key, distance, value))
+            .collect()
+    }
+}
+
+impl<V> Node<V> {
+    fn child(&self, first: char) -> Option<usize> {
+        self.children.iter().position(|x| x.0.starts_with(first))
+    }
+
+    /// Node at the end of path, splitting edges and adding nodes as needed
+    fn node_mut(&mut self, path: &str) -> &mut Self {
         let mut node = self;
-        for c in value.chars() {
-            let idx = node.children_index.entry(c).or_insert_with(|| {
-                node.children.push((c, Self::new()));
-                node.children.len() - 1
-            });
-            node = &mut node.children[*idx].1;
+        let mut pos = 0;
+        while let Some(first) = path[pos..].chars().next() {
+            let Some(idx) = node.child(first) else {
+                node.children.push((path[pos..].into(), Self::default()));
+                return &mut node.children.last_mut().unwrap().1;
+            };
+            let (label, subtrie) = &mut node.children[idx];
+            let common = common_prefix(label, &path[pos..]);
+            if common < label.len() {
+                // Split the edge at the end of the common prefix
+                let rest = std::mem::take(subtrie);
+                subtrie.children.push((label[common..].into(), rest));
+                *label = label[..common].into();
+            }
+            node = &mut node.children[idx].1;
+            pos += common;
         }
-        node.value = Some(value);
+        node
     }
 
-    pub fn get(&self, value: &str) -> Option<&str> {
+    /// Remove the value at the end of path
+    fn remove(&mut self, path: &str) -> Option<V> {
+        let Some(first) = path.chars().next() else {
+            return self.value.take().map(|x| x.1);
+        };
+        let idx = self.child(first)?;
+        let (label, subtrie) = &mut self.children[idx];
+        let rest = path.strip_prefix(&**label)?;
+        let value = subtrie.remove(rest)?;
+        // Prune the branch if nothing is left in it, or merge a single child
+        if subtrie.value.is_none() {
+            match subtrie.children.len() {
+                0 => {
+                    self.children.remove(idx);
+                }
+                1 => {
+                    let (suffix, rest) = subtrie.children.pop().unwrap();
+                    *label = [&**label, &*suffix].concat().into();
+                    *subtrie = rest;
+                }
+                _ => {}
+            }
+        }
+        Some(value)
+    }
+
+    /// Node at the end of path, if any
+    fn node(&self, path: &str) -> Option<&Self> {
+        let mut node = self;
+        let mut rest = path;
+        while let Some(first) = rest.chars().next() {
+            let (label, subtrie) = &node.children[node.child(first)?];
+            rest = rest.strip_prefix(&**label)?;
+            node = subtrie;
+        }
+        Some(node)
+    }
+
+    /// Subtrie containing exactly the keys starting with prefix
+    fn subtrie(&self, prefix: &str) -> Option<&Self> {
         let mut node = self;
-        for c in value.chars() {
-            let idx = node.children_index.get(&c)?;
-            node = &node.children[*idx].1;
+        let mut rest = prefix;
+        while let Some(first) = rest.chars().next() {
+            let (label, subtrie) = &node.children[node.child(first)?];
+            if label.len() >= rest.len() {
+                return label.starts_with(rest).then_some(subtrie);
+            }
+            rest = rest.strip_prefix(&**label)?;
+            node = subtrie;
+        }
+        Some(node)
+    }
+
+    fn iter<'a>(&'a self) -> Box<dyn Iterator<Item = (&'a str, &'a V)> + 'a> {
+        Box::new(
+            self.value
+                .iter()
+                .map(|(k, v)| (k.as_str(), v))
+                .chain(self.children.iter().flat_map(|x| x.1.iter())),
+        )
+    }
+
+    /// Best match within max_edits, also accepted by accept given key and distance
+    fn find_automaton(
+        &self,
+        state: &impl AutomatonState,
+        max_edits: u32,
+        accept: &impl Fn(&str, u32) -> bool,
+    ) -> Option<(&str, u32, &V)> {
+        if !state.can_match(max_edits) {
+            return None;
+        }
+        let this = self
+            .value
+            .as_ref()
+            .map(|(k, v)| (k.as_str(), state.distance(), v))
+            .filter(|x| x.1 <= max_edits && accept(x.0, x.1));
+        self.children.iter().fold(this, |best, (label, subtrie)| {
+            // Method returns some iff best is none or distance is lower
+            best.map_or(Some(max_edits), |x| x.1.checked_sub(1))
+                .and_then(|max_edits| {
+                    let state = step_segment(state, label, max_edits)?;
+                    subtrie.find_automaton(&state, max_edits, accept)
+                })
+                .or(best)
+        })
+    }
+
+    /// Keys with their states, for states that can match within max_edits
+    fn scan_automaton<'a, S: AutomatonState + Clone>(
+        &'a self,
+        state: &S,
+        max_edits: u32,
+        result: &mut Vec<(&'a str, &'a V, S)>,
+    ) {
+        // Some part of the query is within max_edits of the path so far
+        if !state.can_match(max_edits) {
+            return;
+        }
+        if let Some((key, value)) = self.value.as_ref() {
+            result.push((key, value, state.clone()));
+        }
+        for (label, subtrie) in self.children.iter() {
+            if let Some(state) = step_segment(state, label, max_edits) {
+                subtrie.scan_automaton(&state, max_edits, result);
+            }
+        }
+    }
+
+    fn find_all_automaton<'a>(
+        &'a self,
+        state: &impl AutomatonState,
+        max_edits: u32,
+        result: &mut Vec<(&'a str, u32, &'a V)>,
+    ) {
+        if !state.can_match(max_edits) {
+            return;
+        }
+        if let Some((key, value)) = self.value.as_ref() {
+            let distance = state.distance();
+            if distance <= max_edits {
+                result.push((key, distance, value));
+            }
+        }
+        for (label, subtrie) in self.children.iter() {
+            if let Some(state) = step_segment(state, label, max_edits) {
+                subtrie.find_all_automaton(&state, max_edits, result);
+            }
+        }
+    }
+
+    fn find_k_automaton<'a>(
+        &'a self,
+        state: &impl AutomatonState,
+        max_edits: u32,
+        heap: &mut BoundedHeap<Keyed<(u32, &'a str), &'a V>>,
+    ) {
+        // Ties with the current worst may still win on key
+        let bound =
+            |heap: &BoundedHeap<Keyed<(u32, _), _>>| heap.worst().map_or(max_edits, |x| x.0 .0);
+        if !state.can_match(bound(heap)) {
+            return;
+        }
+        if let Some((key, value)) = self.value.as_ref() {
+            let distance = state.distance();
+            if distance <= bound(heap) {
+                heap.push(Keyed((distance, key), value));
+            }
+        }
+        for (label, subtrie) in self.children.iter() {
+            if let Some(state) = step_segment(state, label, bound(heap)) {
+                subtrie.find_k_automaton(&state, max_edits, heap);
+            }
+        }
+    }
+
+    fn fuzzy_prefix_automaton<'a>(
+        &'a self,
+        state: &impl AutomatonState,
+        best: u32,
+        max_edits: u32,
+        heap: &mut BoundedHeap<Keyed<(u32, &'a str), &'a V>>,
+    ) {
+        // Lowest distance of any prefix on the path so far
+        let best = best.min(state.distance());
+        let bound = heap.worst().map_or(max_edits, |x| x.0 .0);
+        // Longer prefixes can only improve on best if the state vector allows
+        if best > bound && !state.can_match(bound) {
+            return;
+        }
+        if let Some((key, value)) = self.value.as_ref() {
+            if best <= bound {
+                heap.push(Keyed((best, key), value));
+            }
+        }
+        for (label, subtrie) in self.children.iter() {
+            let bound = heap.worst().map_or(max_edits, |x| x.0 .0);
+            if let Some((state, best)) = step_prefix_segment(state, label, best, bound) {
+                subtrie.fuzzy_prefix_automaton(&state, best, max_edits, heap);
+            }
+        }
+    }
+}
+
+impl<V> Extend<(String, V)> for TrieMap<V> {
+    fn extend<I: IntoIterator<Item = (String, V)>>(&mut self, iter: I) {
+        for (key, value) in iter {
+            self.insert(key, value);
         }
-        node.value.as_deref()
+    }
+}
+
+impl<V> FromIterator<(String, V)> for TrieMap<V> {
+    fn from_iter<I: IntoIterator<Item = (String, V)>>(iter: I) -> Self {
+        let mut trie = Self::new();
+        trie.extend(iter);
+        trie
+    }
+}
+
+impl<'a, V> IntoIterator for &'a TrieMap<V> {
+    type Item = (&'a str, &'a V);
+    type IntoIter = Box<dyn Iterator<Item = (&'a str, &'a V)> + 'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/// Trie storing the strings to search against
+///
+/// Searches traverse the trie with an automaton, skipping subtries that cannot
+/// contain a sufficiently good match.
+#[derive(Debug, Default, Clone)]
+pub struct Trie(TrieMap<()>);
+
+impl Trie {
+    /// Create an empty trie
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Create an empty trie counting edits in units of segmentation
+    pub fn with_segmentation(segmentation: Segmentation) -> Self {
+        Self(TrieMap::with_segmentation(segmentation))
+    }
+
+    /// Units that edits are counted in
+    pub fn segmentation(&self) -> Segmentation {
+        self.0.segmentation()
+    }
+
+    /// Create an empty trie normalizing values and queries with normalizer
+    pub fn with_normalizer(normalizer: Normalizer) -> Self {
+        Self(TrieMap::with_normalizer(normalizer))
+    }
+
+    /// Create an empty trie with both segmentation and normalizer
+    pub fn with_options(segmentation: Segmentation, normalizer: Normalizer) -> Self {
+        Self(TrieMap::with_options(segmentation, normalizer))
+    }
+
+    /// Normalizer applied to values and queries
+    pub fn normalizer(&self) -> &Normalizer {
+        self.0.normalizer()
+    }
+
+    /// String as read by searches, to build automata for the `_with` methods
+    pub fn search_key<'a>(&self, s: &'a str) -> Cow<'a, str> {
+        self.0.search_key(s)
+    }
+
+    /// Insert value into the trie
+    ///
+    /// # Panics
+    ///
+    /// Panics if the trie has grapheme segmentation and no more chars are
+    /// available for new clusters, see [`try_insert`](Self::try_insert).
+    pub fn insert(&mut self, value: String) {
+        self.0.insert(value, ());
+    }
+
+    /// Insert value into the trie
+    ///
+    /// Fails without inserting if the trie has grapheme segmentation and no
+    /// more chars are available for new clusters.
+    pub fn try_insert(&mut self, value: String) -> Result<(), TooManyClusters> {
+        self.0.try_insert(value, ()).map(|_| ())
+    }
+
+    /// Remove value from the trie, returning whether it was present
+    pub fn remove(&mut self, value: &str) -> bool {
+        self.0.remove(value).is_some()
     }
 
+    /// Get the stored value normalized the same as value
+    pub fn get(&self, value: &str) -> Option<&str> {
+        self.0.get(value).map(|x| x.0)
+    }
+
+    /// Whether the trie contains value
     pub fn contains(&self, value: &str) -> bool {
-        self.get(value).is_some()
+        self.0.contains(value)
     }
 
+    /// All values in the trie
     pub fn values(&self) -> Vec<&str> {
         self.iter().collect()
     }
 
     /// Find best match in trie for query
     pub fn find_one(&self, query: &str, max_edits: Option<u32>) -> Option<(&str, u32)> {
-        let automaton = LevenshteinAutomaton::new(query);
-        self.find_automaton(&automaton.start(), max_edits.unwrap_or(u32::MAX))
+        self.0.find_one(query, max_edits).map(|x| (x.0, x.1))
+    }
+
+    /// Find best match in trie for the query of an automaton
+    pub fn find_one_with(
+        &self,
+        start: &impl AutomatonState,
+        max_edits: Option<u32>,
+    ) -> Option<(&str, u32)> {
+        self.0.find_one_with(start, max_edits).map(|x| (x.0, x.1))
+    }
+
+    /// Find best match in trie for query with the edits transforming query into it
+    pub fn find_one_with_ops(
+        &self,
+        query: &str,
+        max_edits: Option<u32>,
+    ) -> Option<(&str, u32, Vec<Editop>)> {
+        let result = self.0.find_one_with_ops(query, max_edits);
+        result.map(|x| (x.0, x.1, x.3))
+    }
+
+    /// Find best match in trie with at least min_similarity to query
+    pub fn find_one_similar(&self, query: &str, min_similarity: f64) -> Option<(&str, u32)> {
+        let result = self.0.find_one_similar(query, min_similarity);
+        result.map(|x| (x.0, x.1))
+    }
+
+    /// Find best match in trie within the bound for the query of an automaton
+    pub fn find_one_similar_with(
+        &self,
+        start: &impl AutomatonState,
+        bound: &MinSimilarity,
+    ) -> Option<(&str, u32)> {
+        self.0
+            .find_one_similar_with(start, bound)
+            .map(|x| (x.0, x.1))
+    }
+
+    /// Find all matches in trie within max_edits of query
+    ///
+    /// Sorted by distance and then value
+    pub fn find_all(&self, query: &str, max_edits: u32) -> Vec<(&str, u32)> {
+        let result = self.0.find_all(query, max_edits);
+        result.into_iter().map(|x| (x.0, x.1)).collect()
+    }
+
+    /// Find all matches in trie within max_edits of the query of an automaton
+    pub fn find_all_with(&self, start: &impl AutomatonState, max_edits: u32) -> Vec<(&str, u32)> {
+        let result = self.0.find_all_with(start, max_edits);
+        result.into_iter().map(|x| (x.0, x.1)).collect()
+    }
+
+    /// Find the k best matches in trie for query
+    ///
+    /// Sorted by distance and then value
+    pub fn find_k(&self, query: &str, k: usize, max_edits: Option<u32>) -> Vec<(&str, u32)> {
+        let result = self.0.find_k(query, k, max_edits);
+        result.into_iter().map(|x| (x.0, x.1)).collect()
+    }
+
+    /// Find the k best matches in trie for the query of an automaton
+    pub fn find_k_with(
+        &self,
+        start: &impl AutomatonState,
+        k: usize,
+        max_edits: Option<u32>,
+    ) -> Vec<(&str, u32)> {
+        let result = self.0.find_k_with(start, k, max_edits);
+        result.into_iter().map(|x| (x.0, x.1)).collect()
+    }
+
+    /// Find approximate occurrences of values in text within max_edits
+    ///
+    /// Returns (start, end, value, distance) for spans of text, see
+    /// [`TrieMap::scan`].
+    pub fn scan(
+        &self,
+        text: &str,
+        max_edits: u32,
+        selection: Selection,
+    ) -> Vec<(usize, usize, &str, u32)> {
+        let result = self.0.scan(text, max_edits, selection);
+        result.into_iter().map(|x| (x.0, x.1, x.2, x.3)).collect()
+    }
+
+    /// Iterate over all values in the trie
+    pub fn iter<'a>(&'a self) -> Box<dyn Iterator<Item = &'a str> + 'a> {
+        Box::new(self.0.iter().map(|x| x.0))
+    }
+
+    /// All values starting with prefix
+    ///
+    /// Sorted by value
+    pub fn starts_with(&self, prefix: &str) -> Vec<&str> {
+        let result = self.0.starts_with(prefix);
+        result.into_iter().map(|x| x.0).collect()
+    }
+
+    /// Find the best completions in trie with a prefix within max_edits of query
+    ///
+    /// The distance of a value is the lowest distance of any of its prefixes.
+    /// Sorted by distance and then value, with at most limit results.
+    pub fn fuzzy_prefix(
+        &self,
+        query: &str,
+        max_edits: u32,
+        limit: Option<usize>,
+    ) -> Vec<(&str, u32)> {
+        let result = self.0.fuzzy_prefix(query, max_edits, limit);
+        result.into_iter().map(|x| (x.0, x.1)).collect()
+    }
+
+    /// Find the best completions in trie for the query of an automaton
+    pub fn fuzzy_prefix_with(
+        &self,
+        start: &impl AutomatonState,
+        max_edits: u32,
+        limit: Option<usize>,
+    ) -> Vec<(&str, u32)> {
+        let result = self.0.fuzzy_prefix_with(start, max_edits, limit);
+        result.into_iter().map(|x| (x.0, x.1)).collect()
+    }
+
+    /// Serialize the trie to bytes
+    ///
+    /// The segmentation and normalizer are stored first. Values are stored in
+    /// traversal order with the prefix shared with the previous value omitted,
+    /// so that loading recreates the same trie.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = Writer::new(serialize::TRIE);
+        writer.u8(self.segmentation().code());
+        let steps = self.normalizer().steps();
+        writer.varint(steps.len() as u64);
+        for step in steps {
+            let code = Normalization::ALL.iter().position(|x| x == step).unwrap();
+            writer.u8(code as u8);
+        }
+        writer.varint(self.iter().count() as u64);
+        let mut previous = "";
+        for value in self.iter() {
+            let shared: usize = previous
+                .chars()
+                .zip(value.chars())
+                .take_while(|(a, b)| a == b)
+                .map(|(a, _)| a.len_utf8())
+                .sum();
+            writer.varint(shared as u64);
+            writer.str(&value[shared..]);
+            previous = value;
+        }
+        writer.into_bytes()
+    }
+
+    /// Deserialize a trie from bytes created by to_bytes, with the options stored
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, DecodeError> {
+        Self::decode(bytes, None, None)
+    }
+
+    /// Deserialize a trie from bytes created by to_bytes, with segmentation
+    ///
+    /// Returns [`DecodeError::Options`] if the bytes store another
+    /// segmentation, while bytes of version 1 do not store it.
+    pub fn from_bytes_with_segmentation(
+        bytes: &[u8],
+        segmentation: Segmentation,
+    ) -> Result<Self, DecodeError> {
+        Self::decode(bytes, Some(segmentation), None)
+    }
+
+    /// Deserialize a trie from bytes created by to_bytes, with options
+    ///
+    /// Returns [`DecodeError::Options`] if the bytes store other options,
+    /// while bytes of version 1 do not store them.
+    pub fn from_bytes_with_options(
+        bytes: &[u8],
+        segmentation: Segmentation,
+        normalizer: Normalizer,
+    ) -> Result<Self, DecodeError> {
+        Self::decode(bytes, Some(segmentation), Some(normalizer))
+    }
+
+    /// Deserialize a trie with the options given or else those stored
+    pub(crate) fn decode(
+        bytes: &[u8],
+        segmentation: Option<Segmentation>,
+        normalizer: Option<Normalizer>,
+    ) -> Result<Self, DecodeError> {
+        let mut reader = Reader::new(bytes, serialize::TRIE)?;
+        let (mut stored_segmentation, mut stored_normalizer) = (None, None);
+        if reader.version() >= 2 {
+            let segmentation = Segmentation::from_code(reader.u8()?);
+            stored_segmentation = Some(segmentation.ok_or(DecodeError::Invalid)?);
+            let steps = (0..reader.varint()?).map(|_| {
+                let code = reader.u8()? as usize;
+                Normalization::ALL
+                    .get(code)
+                    .copied()
+                    .ok_or(DecodeError::Invalid)
+            });
+            stored_normalizer = Some(steps.collect::<Result<Normalizer, _>>()?);
+        }
+        let segmentation = match (segmentation, stored_segmentation) {
+            (Some(a), Some(b)) if a != b => return Err(DecodeError::Options),
+            (a, b) => a.or(b).unwrap_or_default(),
+        };
+        let normalizer = match (normalizer, stored_normalizer) {
+            (Some(a), Some(b)) if a != b => return Err(DecodeError::Options),
+            (a, b) => a.or(b).unwrap_or_default(),
+        };
+
+        let mut trie = Self::with_options(segmentation, normalizer);
+        let mut previous = String::new();
+        for _ in 0..reader.varint()? {
+            let shared = reader.usize()?;
+            let prefix = previous.get(..shared).ok_or(DecodeError::Invalid)?;
+            let value = prefix.to_string() + reader.str()?;
+            trie.try_insert(value.clone())
+                .map_err(|_| DecodeError::Invalid)?;
+            previous = value;
+        }
+        reader.finish()?;
+        Ok(trie)
     }
 }
 
@@ -87,37 +1057,13 @@ impl<'a> IntoIterator for &'a Trie {
     }
 }
 
-impl Trie {
-    pub fn iter<'a>(&'a self) -> Box<dyn Iterator<Item = &'a str> + 'a> {
-        Box::new(
-            self.value
-                .iter()
-                .map(|v| v.as_str())
-                .chain(self.children.iter().flat_map(|x| x.1.iter())),
-        )
-    }
-
-    fn find_automaton(&self, state: &impl AutomatonState, max_edits: u32) -> Option<(&str, u32)> {
-        if !state.can_match(max_edits) {
-            return None;
-        }
-        let this = self
-            .value
-            .as_ref()
-            .map(|v| (v.as_str(), state.distance()))
-            .filter(|x| x.1 <= max_edits);
-        self.children.iter().fold(this, |best, (next, subtrie)| {
-            // Method returns some iff best is none or distance is lower
-            best.map_or(Some(max_edits), |x| x.1.checked_sub(1))
-                .and_then(|max_edits| subtrie.find_automaton(&state.step(*next), max_edits))
-                .or(best)
-        })
-    }
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::levenshtein::levenshtein;
+    use crate::normalize::Normalization;
+    use crate::osa::OsaAutomaton;
+    use crate::similarity::similarity;
     use std::collections::HashSet;
 
     #[test]
@@ -161,4 +1107,482 @@ mod tests {
         assert_eq!(trie.find_one("baz", None), Some(("bar", 1)));
         assert_eq!(trie.find_one("baz", Some(0)), None);
     }
+
+    #[test]
+    fn find_with_ops() {
+        use crate::editops::EditKind;
+
+        let trie = Trie::from_iter(["kitten", "bar"].map(String::from));
+        let (value, distance, ops) = trie.find_one_with_ops("sitting", None).unwrap();
+        assert_eq!((value, distance), ("kitten", 3));
+        let kinds: Vec<_> = ops.iter().map(|x| (x.kind, x.src, x.dest)).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                (EditKind::Replace, 0, 0),
+                (EditKind::Replace, 4, 4),
+                (EditKind::Delete, 6, 6),
+            ]
+        );
+        assert_eq!(
+            trie.find_one_with_ops("bar", Some(0)),
+            Some(("bar", 0, vec![]))
+        );
+        assert_eq!(trie.find_one_with_ops("xyzzy", Some(1)), None);
+
+        // Positions are in chars as searched
+        let mut trie = Trie::with_normalizer(Normalizer::from(Normalization::Lowercase));
+        trie.insert("Straße".to_string());
+        let (_, _, ops) = trie.find_one_with_ops("STRASE", None).unwrap();
+        assert_eq!(ops.len(), 1);
+        assert_eq!((ops[0].src, ops[0].dest), (4, 4));
+    }
+
+    #[test]
+    fn find_similar() {
+        let trie = Trie::from_iter(["ab", "abcdefgh", "foo", "foobar", "ba"].map(String::from));
+        assert_eq!(trie.find_one_similar("ac", 0.5), Some(("ab", 1)));
+        assert_eq!(trie.find_one_similar("ac", 0.6), None);
+        assert_eq!(
+            trie.find_one_similar("abcdefx", 0.75),
+            Some(("abcdefgh", 2))
+        );
+        let bound = MinSimilarity::new("abcdefx", 0.75).with_max_edits(1);
+        assert_eq!(
+            trie.find_one_similar_with(&LevenshteinAutomaton::new("abcdefx").start(), &bound),
+            None
+        );
+        for min in [0.0, 0.3, 0.5, 0.7, 0.9, 1.0] {
+            for query in ["", "a", "fo", "abcdef", "fooba", "bar", "xyz"] {
+                let brute = trie
+                    .iter()
+                    .map(|x| (levenshtein(query, x), x))
+                    .filter(|&(d, x)| {
+                        similarity(d, query.chars().count(), x.chars().count()) >= min
+                    })
+                    .map(|x| x.0)
+                    .min();
+                assert_eq!(trie.find_one_similar(query, min).map(|x| x.1), brute);
+            }
+        }
+    }
+
+    #[test]
+    fn find_all() {
+        let trie = Trie::from_iter(["foo", "bar", "baz", "ba", "qux"].map(String::from));
+        assert_eq!(trie.find_all("", 1), vec![]);
+        assert_eq!(trie.find_all("baz", 0), vec![("baz", 0)]);
+        assert_eq!(
+            trie.find_all("baz", 1),
+            vec![("baz", 0), ("ba", 1), ("bar", 1)]
+        );
+        assert_eq!(trie.find_all("baz", 3).len(), 5);
+    }
+
+    #[test]
+    fn find_k() {
+        let trie = Trie::from_iter(["foo", "bar", "baz", "ba", "qux"].map(String::from));
+        assert_eq!(trie.find_k("baz", 0, None), vec![]);
+        assert_eq!(trie.find_k("baz", 1, None), vec![("baz", 0)]);
+        assert_eq!(
+            trie.find_k("baz", 3, None),
+            vec![("baz", 0), ("ba", 1), ("bar", 1)]
+        );
+        assert_eq!(trie.find_k("bay", 2, None), vec![("ba", 1), ("bar", 1)]);
+        assert_eq!(trie.find_k("bay", 10, Some(1)).len(), 3);
+        assert_eq!(trie.find_k("bay", 10, None).len(), 5);
+    }
+
+    #[test]
+    fn find_osa() {
+        let trie = Trie::from_iter(["the", "tea", "then"].map(String::from));
+        assert_eq!(trie.find_one("teh", None), Some(("tea", 1)));
+        let automaton = OsaAutomaton::new("teh");
+        assert_eq!(
+            trie.find_one_with(&automaton.start(), None),
+            Some(("the", 1))
+        );
+        assert_eq!(
+            trie.find_all_with(&automaton.start(), 1),
+            vec![("tea", 1), ("the", 1)]
+        );
+    }
+
+    #[test]
+    fn remove() {
+        let mut trie = Trie::from_iter(["foo", "bar", "baz", "ba", ""].map(String::from));
+        assert!(!trie.remove("b"));
+        assert!(!trie.remove("quux"));
+
+        assert!(trie.remove(""));
+        assert!(!trie.remove(""));
+        assert!(!trie.contains(""));
+        assert!(trie.contains("ba"));
+
+        assert!(trie.remove("ba"));
+        assert!(trie.contains("bar"));
+        assert!(trie.contains("baz"));
+        assert!(trie.remove("bar"));
+        assert_eq!(trie.get("baz"), Some("baz"));
+        assert_eq!(trie.find_one("bar", None), Some(("baz", 1)));
+
+        assert!(trie.remove("foo"));
+        // Branch left with a single child is merged into one edge
+        assert_eq!(trie.0.root.children.len(), 1);
+        assert_eq!(&*trie.0.root.children[0].0, "baz");
+        assert!(trie.remove("baz"));
+        assert!(trie.0.root.children.is_empty());
+        assert_eq!(trie.iter().count(), 0);
+
+        trie.insert("foo".to_string());
+        assert!(trie.contains("foo"));
+    }
+
+    #[test]
+    fn segments() {
+        let mut trie = Trie::from_iter(["foobar", "föo"].map(String::from));
+        let labels = |node: &Node<()>| -> Vec<String> {
+            node.children.iter().map(|x| x.0.to_string()).collect()
+        };
+        assert_eq!(labels(&trie.0.root), vec!["f"]);
+        assert_eq!(labels(&trie.0.root.children[0].1), vec!["oobar", "öo"]);
+
+        trie.insert("foo".to_string());
+        assert!(trie.contains("foo"));
+        assert!(!trie.contains("fo"));
+        assert!(!trie.contains("fooba"));
+        assert_eq!(trie.get("foobar"), Some("foobar"));
+        assert_eq!(trie.starts_with("foob"), vec!["foobar"]);
+        assert_eq!(trie.find_one("fooba", None), Some(("foobar", 1)));
+        assert_eq!(trie.find_all("fo", 1), vec![("foo", 1), ("föo", 1)]);
+        assert_eq!(
+            trie.find_k("foobaz", 2, None),
+            vec![("foobar", 1), ("foo", 3)]
+        );
+    }
+
+    #[test]
+    fn graphemes() {
+        let mut trie = Trie::with_segmentation(Segmentation::Graphemes);
+        trie.extend(["cafe\u{301}", "café", "cafe", "👍🏽"].map(String::from));
+        assert_eq!(trie.segmentation(), Segmentation::Graphemes);
+        // Edge labels split between clusters only
+        assert_eq!(trie.0.root.children[0].0.as_ref(), "caf");
+        let labels = &trie.0.root.children[0].1.children;
+        assert_eq!(labels.len(), 3);
+        assert!(labels.iter().all(|x| x.0.chars().count() == 1));
+
+        assert_eq!(trie.get("cafe\u{301}"), Some("cafe\u{301}"));
+        assert!(!trie.contains("caf"));
+        assert!(!trie.contains("cafa\u{301}"));
+        assert_eq!(trie.starts_with("cafe"), vec!["cafe"]);
+        assert_eq!(trie.find_one("cafa\u{301}", None), Some(("cafe\u{301}", 1)));
+        assert_eq!(trie.find_one("👍", None), Some(("👍🏽", 1)));
+        assert_eq!(trie.find_all("cafe\u{301}", 0), vec![("cafe\u{301}", 0)]);
+        assert_eq!(trie.fuzzy_prefix("cafe", 0, None), vec![("cafe", 0)]);
+        assert_eq!(
+            trie.find_one_similar("cafa\u{301}", 0.75),
+            Some(("cafe\u{301}", 1))
+        );
+        let query = trie.search_key("cafa\u{301}");
+        let automaton = OsaAutomaton::new(&query);
+        assert_eq!(
+            trie.find_k_with(&automaton.start(), 1, None),
+            vec![("cafe", 1)]
+        );
+
+        let codepoints = Trie::from_iter(trie.iter().map(String::from));
+        assert_eq!(codepoints.starts_with("cafe"), vec!["cafe", "cafe\u{301}"]);
+        assert_eq!(
+            codepoints.find_one("cafa\u{301}", None),
+            Some(("cafe\u{301}", 1))
+        );
+        assert_eq!(codepoints.find_one("👍", None), Some(("👍🏽", 1)));
+        assert_eq!(
+            codepoints.find_one_similar("cafa\u{301}", 0.8),
+            Some(("cafe\u{301}", 1))
+        );
+        assert_eq!(trie.find_one_similar("cafa\u{301}", 0.8), None);
+
+        let bytes = trie.to_bytes();
+        let loaded = Trie::from_bytes_with_segmentation(&bytes, Segmentation::Graphemes).unwrap();
+        assert_eq!(loaded.values(), trie.values());
+        assert_eq!(loaded.starts_with("cafe"), vec!["cafe"]);
+
+        assert!(trie.remove("cafe\u{301}"));
+        assert!(!trie.contains("cafe\u{301}"));
+        assert!(trie.contains("café"));
+        assert!(!trie.remove("cafa\u{301}"));
+    }
+
+    #[test]
+    fn too_many_graphemes() {
+        let mut trie = Trie::with_segmentation(Segmentation::Graphemes);
+        trie.insert("foo".to_string());
+        // More distinct clusters than private use chars
+        let value: String = ('\u{4e00}'..)
+            .flat_map(|c| ('\u{300}'..'\u{370}').flat_map(move |m| [c, m]))
+            .take(2 * 0x20000)
+            .collect();
+        assert_eq!(trie.try_insert(value.clone()), Err(TooManyClusters));
+        assert!(!trie.contains(&value));
+        assert_eq!(trie.values(), vec!["foo"]);
+        assert_eq!(trie.try_insert("bar".to_string()), Ok(()));
+    }
+
+    #[test]
+    fn normalized() {
+        let normalizer = Normalizer::new([Normalization::Nfkc, Normalization::Lowercase]);
+        let mut trie = TrieMap::with_normalizer(normalizer.clone());
+        assert_eq!(trie.insert("Foo".to_string(), 1), None);
+        trie.insert("ＢＡＲ".to_string(), 2);
+        trie.insert("baz".to_string(), 3);
+        assert_eq!(trie.normalizer(), &normalizer);
+        assert_eq!(trie.get("FOO"), Some(("Foo", &1)));
+        assert_eq!(trie.get("bar"), Some(("ＢＡＲ", &2)));
+        assert_eq!(trie.get("fo"), None);
+        assert_eq!(trie.find_one("BAY", None), Some(("ＢＡＲ", 1, &2)));
+        assert_eq!(trie.find_all("FOX", 1), vec![("Foo", 1, &1)]);
+        assert_eq!(trie.starts_with("BA"), vec![("baz", &3), ("ＢＡＲ", &2)]);
+        // Lengths for similarity are those of the normalized keys
+        trie.insert("ﬃ".to_string(), 5);
+        assert_eq!(trie.find_one_similar("FF", 0.6), Some(("ﬃ", 1, &5)));
+        // Keys normalized the same replace each other
+        assert_eq!(trie.insert("FOO".to_string(), 4), Some(1));
+        assert_eq!(trie.get("foo"), Some(("FOO", &4)));
+        assert_eq!(trie.remove("fOo"), Some(4));
+        assert!(!trie.contains("foo"));
+
+        let mut trie = Trie::with_options(
+            Segmentation::Graphemes,
+            Normalizer::from(Normalization::StripAccents),
+        );
+        trie.extend(["Crème".to_string(), "cafe\u{301}".to_string()]);
+        assert_eq!(trie.get("Creme"), Some("Crème"));
+        assert_eq!(trie.find_one("cafè", Some(0)), Some(("cafe\u{301}", 0)));
+        assert_eq!(trie.search_key("Crème"), "Creme");
+        let bytes = trie.to_bytes();
+        let loaded = Trie::from_bytes_with_options(
+            &bytes,
+            Segmentation::Graphemes,
+            trie.normalizer().clone(),
+        )
+        .unwrap();
+        assert_eq!(loaded.values(), trie.values());
+        assert_eq!(loaded.get("creme"), None);
+        assert_eq!(loaded.get("Creme"), Some("Crème"));
+        // Options are stored with the values
+        let loaded = Trie::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded.segmentation(), Segmentation::Graphemes);
+        assert_eq!(loaded.normalizer(), trie.normalizer());
+        assert_eq!(loaded.get("Creme"), Some("Crème"));
+        assert_eq!(
+            Trie::from_bytes_with_options(&bytes, Segmentation::Graphemes, Normalizer::default())
+                .err(),
+            Some(DecodeError::Options)
+        );
+        assert_eq!(
+            Trie::from_bytes_with_segmentation(&bytes, Segmentation::Codepoints).err(),
+            Some(DecodeError::Options)
+        );
+    }
+
+    #[test]
+    fn scan() {
+        let trie = Trie::from_iter(["new", "new york", "york", "acme"].map(String::from));
+        let text = "in new yrok, acme and acne";
+        assert_eq!(
+            trie.scan(text, 0, Selection::All),
+            vec![(3, 6, "new", 0), (13, 17, "acme", 0)]
+        );
+        assert_eq!(
+            trie.scan(text, 1, Selection::NonOverlapping),
+            vec![(3, 6, "new", 0), (13, 17, "acme", 0), (22, 26, "acme", 1)]
+        );
+        // Lower distance wins over length
+        let spans = trie.scan(text, 2, Selection::NonOverlapping);
+        assert!(spans.contains(&(3, 6, "new", 0)));
+        assert!(spans.windows(2).all(|x| x[0].1 <= x[1].0));
+        let longest = trie.scan(text, 2, Selection::Longest);
+        assert!(longest.contains(&(3, 11, "new york", 2)));
+        assert!(!longest.contains(&(3, 6, "new", 0)));
+        assert_eq!(longest.iter().filter(|x| x.0 == 13).count(), 1);
+
+        // Spans are between clusters of the original text
+        let mut trie = TrieMap::with_options(
+            Segmentation::Graphemes,
+            Normalizer::new([Normalization::Casefold, Normalization::StripAccents]),
+        );
+        trie.insert("cafe".to_string(), 1);
+        trie.insert(String::new(), 2);
+        let text = "Un CAFE\u{301} noir, un Café";
+        assert_eq!(
+            trie.scan(text, 0, Selection::All),
+            vec![(3, 9, "cafe", 0, &1), (19, 24, "cafe", 0, &1)]
+        );
+
+        // Same spans as trying every key from every start
+        let keys = ["a", "ab", "abc", "bca", "cab", "aaaa", "quick", "brown fox"];
+        let trie = Trie::from_iter(keys.map(String::from));
+        for text in [
+            "",
+            "abcabcab",
+            "the quick brown fox",
+            "aaaaaa",
+            "bacabxacab",
+            "the quick brown fox jumps over the lazy dog, a quick brown cat naps",
+        ] {
+            for max_edits in 0..3 {
+                let mut brute = Vec::new();
+                for (start, _) in text.char_indices() {
+                    for key in keys {
+                        let best = text[start..]
+                            .char_indices()
+                            .map(|(i, c)| start + i + c.len_utf8())
+                            .map(|end| (levenshtein(key, &text[start..end]), end))
+                            .min_by_key(|x| (x.0, Reverse(x.1)));
+                        if let Some((distance, end)) = best.filter(|x| x.0 <= max_edits) {
+                            brute.push((start, end, key, distance));
+                        }
+                    }
+                }
+                brute.sort_unstable();
+                let mut found = trie.scan(text, max_edits, Selection::All);
+                found.sort_unstable();
+                assert_eq!(found, brute, "{text} {max_edits}");
+            }
+        }
+    }
+
+    #[test]
+    fn segments_random() {
+        // Deterministic pseudo-random strings over a small alphabet
+        let mut seed = 12345u64;
+        let mut random_string = || {
+            seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+            let len = (seed >> 61) as usize;
+            (0..len)
+                .map(|_| {
+                    seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1);
+                    (b'a' + (seed >> 60) as u8 % 3) as char
+                })
+                .collect::<String>()
+        };
+        let values: Vec<String> = (0..200).map(|_| random_string()).collect();
+        let mut trie = Trie::from_iter(values.iter().cloned());
+        for value in values.iter().step_by(3) {
+            trie.remove(value);
+        }
+        let removed: Vec<&String> = values.iter().step_by(3).collect();
+        let mut expected: Vec<&str> = values
+            .iter()
+            .filter(|x| !removed.contains(x))
+            .map(|x| x.as_str())
+            .collect();
+        expected.sort_unstable();
+        expected.dedup();
+        let mut actual = trie.values();
+        actual.sort_unstable();
+        assert_eq!(actual, expected);
+        for _ in 0..20 {
+            let query = random_string();
+            let mut brute: Vec<_> = expected
+                .iter()
+                .map(|x| (*x, crate::levenshtein::levenshtein(&query, x)))
+                .collect();
+            brute.sort_unstable_by_key(|x| (x.1, x.0));
+            let best = trie.find_one(&query, None).map(|x| x.1);
+            assert_eq!(best, brute.first().map(|x| x.1));
+            brute.retain(|x| x.1 <= 2);
+            assert_eq!(trie.find_all(&query, 2), brute);
+        }
+    }
+
+    #[test]
+    fn starts_with() {
+        let trie = Trie::from_iter(["foo", "fob", "bar", "f", "fo"].map(String::from));
+        assert_eq!(trie.starts_with("fo"), vec!["fo", "fob", "foo"]);
+        assert_eq!(trie.starts_with(""), vec!["bar", "f", "fo", "fob", "foo"]);
+        assert_eq!(trie.starts_with("foo"), vec!["foo"]);
+        assert_eq!(trie.starts_with("fooo"), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn fuzzy_prefix() {
+        let trie = Trie::from_iter(
+            ["apple", "application", "apply", "banana", "ape", "a"].map(String::from),
+        );
+        assert_eq!(
+            trie.fuzzy_prefix("apl", 1, None),
+            vec![("ape", 1), ("apple", 1), ("application", 1), ("apply", 1)]
+        );
+        assert_eq!(
+            trie.fuzzy_prefix("appl", 0, None),
+            vec![("apple", 0), ("application", 0), ("apply", 0)]
+        );
+        assert_eq!(
+            trie.fuzzy_prefix("appli", 1, Some(2)),
+            vec![("application", 0), ("apple", 1)]
+        );
+        assert_eq!(trie.fuzzy_prefix("xyz", 1, None), vec![]);
+        assert_eq!(trie.fuzzy_prefix("", 0, Some(1)), vec![("a", 0)]);
+        for (value, distance) in trie.fuzzy_prefix("banan", 2, None) {
+            let best = (0..=value.len())
+                .map(|i| crate::levenshtein::levenshtein("banan", &value[..i]))
+                .min()
+                .unwrap();
+            assert_eq!(distance, best);
+        }
+    }
+
+    #[test]
+    fn bytes() {
+        let trie = Trie::from_iter(["foo", "", "bar", "föo", "fö", "baz"].map(String::from));
+        let bytes = trie.to_bytes();
+        let loaded = Trie::from_bytes(&bytes).unwrap();
+        assert_eq!(loaded.values(), trie.values());
+        assert_eq!(loaded.find_one("fo", None), Some(("foo", 1)));
+
+        assert_eq!(
+            Trie::from_bytes(&bytes[..bytes.len() - 1]).err(),
+            Some(DecodeError::Truncated)
+        );
+        assert_eq!(Trie::from_bytes(b"").err(), Some(DecodeError::Header));
+        let empty = Trie::new().to_bytes();
+        assert_eq!(
+            Trie::from_bytes(&empty).unwrap().values(),
+            Vec::<&str>::new()
+        );
+
+        // Version 1 does not store the options
+        let header = serialize::HEADER_LEN;
+        let mut old = bytes[..header].to_vec();
+        old[header - 1] = 1;
+        old.extend(&bytes[header + 2..]);
+        let loaded = Trie::from_bytes(&old).unwrap();
+        assert_eq!(loaded.values(), trie.values());
+        let normalizer = Normalizer::from(Normalization::Lowercase);
+        let loaded =
+            Trie::from_bytes_with_options(&old, Segmentation::Graphemes, normalizer).unwrap();
+        assert_eq!(loaded.segmentation(), Segmentation::Graphemes);
+        assert_eq!(loaded.get("FOO"), Some("foo"));
+    }
+
+    #[test]
+    fn map() {
+        let mut map = TrieMap::new();
+        assert_eq!(map.insert("foo".to_string(), 1), None);
+        assert_eq!(map.insert("bar".to_string(), 2), None);
+        assert_eq!(map.insert("foo".to_string(), 3), Some(1));
+        assert_eq!(map.get("foo"), Some(("foo", &3)));
+        assert_eq!(map.find_one("baz", None), Some(("bar", 1, &2)));
+        assert_eq!(map.find_all("fo", 1), vec![("foo", 1, &3)]);
+        assert_eq!(
+            map.find_k("baz", 2, None),
+            vec![("bar", 1, &2), ("foo", 3, &3)]
+        );
+        assert_eq!(map.remove("foo"), Some(3));
+        assert_eq!(map.remove("foo"), None);
+        assert_eq!(map.iter().collect::<Vec<_>>(), vec![("bar", &2)]);
+    }
 }
@@ -0,0 +1,623 @@
+//! Levenshtein distance with weighted edit operations
+
+use std::collections::HashMap;
+
+use crate::levenshtein::AutomatonState;
+
+/// Costs of the edit operations transforming one string into another
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Weights {
+    /// Cost of inserting a character
+    pub insert: u32,
+    /// Cost of deleting a character
+    pub delete: u32,
+    /// Cost of substituting a character for another
+    pub substitute: u32,
+}
+
+impl Weights {
+    /// Create weights for insertion, deletion and substitution
+    pub fn new(insert: u32, delete: u32, substitute: u32) -> Self {
+        Self {
+            insert,
+            delete,
+            substitute,
+        }
+    }
+}
+
+impl Default for Weights {
+    fn default() -> Self {
+        Self::new(1, 1, 1)
+    }
+}
+
+/// Costs of substituting pairs of characters, with a default for other pairs
+///
+/// Substitutions between strings of different numbers of characters, such as
+/// "rn" for "m", can be added with [`insert_str`](Self::insert_str).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Substitutions {
+    default: u32,
+    costs: HashMap<(char, char), u32>,
+    // Substitutions of more than one char, in both directions
+    sequences: Vec<(Vec<char>, Vec<char>, u32)>,
+}
+
+impl Substitutions {
+    /// Create an empty table where every substitution costs default
+    pub fn new(default: u32) -> Self {
+        Self {
+            default,
+            costs: HashMap::new(),
+            sequences: Vec::new(),
+        }
+    }
+
+    /// Set the cost of substituting a for b, in either direction
+    pub fn insert(&mut self, a: char, b: char, cost: u32) {
+        self.costs.insert((a, b), cost);
+        self.costs.insert((b, a), cost);
+    }
+
+    /// Set the cost of substituting string a for string b, in either direction
+    ///
+    /// Single chars are as for insert, while longer strings are substituted
+    /// as a whole. Panics if either string is empty.
+    pub fn insert_str(&mut self, a: &str, b: &str, cost: u32) {
+        assert!(
+            !a.is_empty() && !b.is_empty(),
+            "substitution of empty string"
+        );
+        let a: Vec<char> = a.chars().collect();
+        let b: Vec<char> = b.chars().collect();
+        if let ([a], [b]) = (a.as_slice(), b.as_slice()) {
+            return self.insert(*a, *b, cost);
+        }
+        self.sequences
+            .retain(|(x, y, _)| (x, y) != (&a, &b) && (x, y) != (&b, &a));
+        if a != b {
+            self.sequences.push((b.clone(), a.clone(), cost));
+        }
+        self.sequences.push((a, b, cost));
+    }
+
+    /// Cost of substituting a for b, zero if they are equal
+    pub fn cost(&self, a: char, b: char) -> u32 {
+        if a == b {
+            0
+        } else {
+            *self.costs.get(&(a, b)).unwrap_or(&self.default)
+        }
+    }
+
+    /// Cost of substituting string a for string b as a whole, zero if they
+    /// are equal
+    pub fn cost_str(&self, a: &str, b: &str) -> u32 {
+        let a: Vec<char> = a.chars().collect();
+        let b: Vec<char> = b.chars().collect();
+        if let ([a], [b]) = (a.as_slice(), b.as_slice()) {
+            return self.cost(*a, *b);
+        }
+        match self.sequences.iter().find(|(x, y, _)| (x, y) == (&a, &b)) {
+            _ if a == b => 0,
+            Some((_, _, cost)) => *cost,
+            None => self.default,
+        }
+    }
+
+    /// Substitutions of keys adjacent on a QWERTY keyboard cost adjacent
+    ///
+    /// Only the digit and letter keys without modifiers are included, along
+    /// with the corresponding pairs of uppercase letters.
+    pub fn qwerty(adjacent: u32, default: u32) -> Self {
+        let rows: Vec<Vec<char>> = ["1234567890", "qwertyuiop", "asdfghjkl", "zxcvbnm"]
+            .iter()
+            .map(|x| x.chars().collect())
+            .collect();
+        let mut table = Self::new(default);
+        let mut insert = |a: char, b: char| {
+            table.insert(a, b, adjacent);
+            if a.is_alphabetic() && b.is_alphabetic() {
+                table.insert(a.to_ascii_uppercase(), b.to_ascii_uppercase(), adjacent);
+            }
+        };
+        for (i, row) in rows.iter().enumerate() {
+            for (j, &a) in row.iter().enumerate() {
+                if let Some(&b) = row.get(j + 1) {
+                    insert(a, b);
+                }
+                // Rows are staggered, each key is below the two keys above it
+                if let Some(above) = i.checked_sub(1).map(|x| &rows[x]) {
+                    for &b in &above[j..(j + 2).min(above.len())] {
+                        insert(a, b);
+                    }
+                }
+            }
+        }
+        table
+    }
+
+    /// Substitutions of characters commonly confused by OCR cost confusable
+    ///
+    /// Includes confusions between different numbers of characters, such as
+    /// "rn" and "m".
+    pub fn ocr(confusable: u32, default: u32) -> Self {
+        let mut table = Self::new(default);
+        for (a, b) in [
+            ('0', 'O'),
+            ('0', 'o'),
+            ('0', 'D'),
+            ('O', 'D'),
+            ('O', 'Q'),
+            ('1', 'l'),
+            ('1', 'I'),
+            ('1', '7'),
+            ('l', 'I'),
+            ('l', '|'),
+            ('I', '|'),
+            ('2', 'Z'),
+            ('5', 'S'),
+            ('6', 'G'),
+            ('6', 'b'),
+            ('8', 'B'),
+            ('9', 'g'),
+            ('9', 'q'),
+            ('c', 'e'),
+            ('c', 'o'),
+            ('e', 'o'),
+            ('a', 'o'),
+            ('h', 'b'),
+            ('h', 'n'),
+            ('m', 'n'),
+            ('n', 'u'),
+            ('u', 'v'),
+            ('v', 'y'),
+            ('f', 't'),
+            ('i', 'l'),
+            ('i', 'j'),
+            (',', '.'),
+        ] {
+            table.insert(a, b, confusable);
+        }
+        for (a, b) in [
+            ("rn", "m"),
+            ("cl", "d"),
+            ("vv", "w"),
+            ("nn", "m"),
+            ("li", "h"),
+        ] {
+            table.insert_str(a, b, confusable);
+        }
+        table
+    }
+}
+
+impl Extend<((char, char), u32)> for Substitutions {
+    fn extend<I: IntoIterator<Item = ((char, char), u32)>>(&mut self, iter: I) {
+        for ((a, b), cost) in iter {
+            self.insert(a, b, cost);
+        }
+    }
+}
+
+/// Automaton for the weighted Levenshtein distance from a string
+///
+/// Insertions and deletions are from the point of view of transforming the
+/// string of the automaton into the string read.
+#[derive(Debug, Clone)]
+pub struct WeightedAutomaton<'a> {
+    string: &'a str,
+    weights: Weights,
+    substitutions: Option<&'a Substitutions>,
+    // Substitutions of more than one char occurring in the string
+    sequences: Vec<Sequence<'a>>,
+    // Number of rows before the current one that sequences reach back to
+    window: usize,
+}
+
+/// Substitution of the chars of the string ending at end for the chars read
+#[derive(Debug, Clone)]
+struct Sequence<'a> {
+    end: usize,
+    len: usize,
+    read: &'a [char],
+    cost: u32,
+}
+
+impl<'a> WeightedAutomaton<'a> {
+    /// Create the automaton for string with weights
+    pub fn new(string: &'a str, weights: Weights) -> Self {
+        Self {
+            string,
+            weights,
+            substitutions: None,
+            sequences: Vec::new(),
+            window: 0,
+        }
+    }
+
+    /// Create the automaton with substitution costs from a table
+    ///
+    /// The table replaces the substitution weight.
+    pub fn with_substitutions(
+        string: &'a str,
+        weights: Weights,
+        substitutions: &'a Substitutions,
+    ) -> Self {
+        let chars: Vec<char> = string.chars().collect();
+        let mut sequences = Vec::new();
+        for (a, read, cost) in &substitutions.sequences {
+            for end in a.len()..=chars.len() {
+                if chars[end - a.len()..end] == a[..] {
+                    sequences.push(Sequence {
+                        end,
+                        len: a.len(),
+                        read,
+                        cost: *cost,
+                    });
+                }
+            }
+        }
+        let window = sequences.iter().map(|x| x.read.len() - 1).max();
+        Self {
+            string,
+            weights,
+            substitutions: Some(substitutions),
+            sequences,
+            window: window.unwrap_or(0),
+        }
+    }
+
+    /// Initial state of the automaton
+    pub fn start(&self) -> WeightedState<'_> {
+        let delete = self.weights.delete;
+        WeightedState {
+            m: self,
+            v: std::iter::successors(Some(0u32), |x| Some(x.saturating_add(delete)))
+                .take(self.string.chars().count() + 1)
+                .collect(),
+            past: Vec::new(),
+        }
+    }
+
+    /// Weighted distance from the string of the automaton to other
+    pub fn distance(&self, other: &str) -> u32 {
+        let mut state = self.start();
+        for value in other.chars() {
+            state.step_mut(value);
+        }
+        state.distance()
+    }
+
+    /// Weighted distance from the string of the automaton to other, if at
+    /// most max_edits
+    pub fn distance_within(&self, other: &str, max_edits: u32) -> Option<u32> {
+        // Difference in length needs either deletions or insertions
+        let len = self.string.chars().count();
+        let other_len = other.chars().count();
+        let cost = if len > other_len {
+            self.weights.delete
+        } else {
+            self.weights.insert
+        };
+        let min = u32::try_from(len.abs_diff(other_len)).unwrap_or(u32::MAX);
+        // Substitutions of more than one char can also change the length
+        if self.sequences.is_empty() && min.saturating_mul(cost) > max_edits {
+            return None;
+        }
+        self.start().distance_within(other, max_edits)
+    }
+}
+
+/// State of a weighted Levenshtein automaton, as a dynamic programming row
+#[derive(Debug, Clone)]
+pub struct WeightedState<'a> {
+    m: &'a WeightedAutomaton<'a>,
+    v: Vec<u32>,
+    // Previous rows with the char read after each, as far as sequences reach
+    past: Vec<(Vec<u32>, char)>,
+}
+
+impl WeightedState<'_> {
+    /// Costs at each position of substituting a sequence for the latest chars
+    /// read, ending with value
+    fn sequences(&self, value: char) -> Vec<u32> {
+        let mut costs = vec![u32::MAX; self.v.len()];
+        for x in &self.m.sequences {
+            let Some(start) = (self.past.len() + 1).checked_sub(x.read.len()) else {
+                continue;
+            };
+            let (read, last) = x.read.split_at(x.read.len() - 1);
+            let past = &self.past[start..];
+            if last[0] != value || past.iter().zip(read).any(|((_, c), r)| c != r) {
+                continue;
+            }
+            let row = past.first().map_or(&self.v, |x| &x.0);
+            let cost = row[x.end - x.len].saturating_add(x.cost);
+            costs[x.end] = costs[x.end].min(cost);
+        }
+        costs
+    }
+}
+
+impl AutomatonState for WeightedState<'_> {
+    fn step_mut(&mut self, value: char) {
+        let Weights {
+            insert,
+            delete,
+            substitute,
+        } = self.m.weights;
+        let sequences = if self.m.sequences.is_empty() {
+            Vec::new()
+        } else {
+            self.sequences(value)
+        };
+        if self.m.window > 0 {
+            if self.past.len() == self.m.window {
+                self.past.remove(0);
+            }
+            self.past.push((self.v.clone(), value));
+        }
+        let mut sub = self.v[0];
+        let mut add = sub.saturating_add(insert);
+        let mut del;
+        self.v[0] = add;
+        for (i, c) in self.m.string.chars().enumerate() {
+            del = self.v[i + 1];
+            sub = match self.m.substitutions {
+                _ if c == value => sub,
+                Some(table) => sub.saturating_add(table.cost(c, value)),
+                None => sub.saturating_add(substitute),
+            };
+            add = sub
+                .min(add.saturating_add(delete))
+                .min(del.saturating_add(insert));
+            if let Some(&cost) = sequences.get(i + 1) {
+                add = add.min(cost);
+            }
+            sub = del;
+            self.v[i + 1] = add;
+        }
+    }
+
+    fn step(&self, value: char) -> Self {
+        let mut new = self.clone();
+        new.step_mut(value);
+        new
+    }
+
+    fn distance(&self) -> u32 {
+        *self.v.last().unwrap()
+    }
+
+    fn can_match(&self, max_edits: u32) -> bool {
+        // Costs are non-negative, so no entry of the rows can decrease
+        let past = self.past.iter().flat_map(|x| &x.0);
+        self.v.iter().chain(past).min().unwrap() <= &max_edits
+    }
+}
+
+/// Find the weighted Levenshtein distance transforming a into b
+pub fn weighted_levenshtein(a: &str, b: &str, weights: Weights) -> u32 {
+    WeightedAutomaton::new(a, weights).distance(b)
+}
+
+/// Find the weighted Levenshtein distance with substitution costs from a table
+pub fn substitution_levenshtein(
+    a: &str,
+    b: &str,
+    weights: Weights,
+    substitutions: &Substitutions,
+) -> u32 {
+    WeightedAutomaton::with_substitutions(a, weights, substitutions).distance(b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::levenshtein::levenshtein;
+    use crate::trie::Trie;
+
+    #[test]
+    fn distances() {
+        let weights = Weights::new(1, 1, 1);
+        for (a, b) in [
+            ("foo", "bar"),
+            ("", "bar"),
+            ("kitten", "sitting"),
+            ("ab", "aacbb"),
+        ] {
+            assert_eq!(weighted_levenshtein(a, b, weights), levenshtein(a, b));
+        }
+
+        let weights = Weights::new(2, 3, 4);
+        assert_eq!(weighted_levenshtein("", "ab", weights), 4);
+        assert_eq!(weighted_levenshtein("ab", "", weights), 6);
+        assert_eq!(weighted_levenshtein("ab", "ac", weights), 4);
+        assert_eq!(weighted_levenshtein("abc", "abc", weights), 0);
+
+        // Substitution replaced by deletion and insertion when cheaper
+        let weights = Weights::new(1, 1, 5);
+        assert_eq!(weighted_levenshtein("ab", "ac", weights), 2);
+        let automaton = WeightedAutomaton::new("ab", Weights::new(2, 3, 4));
+        assert_eq!(automaton.distance_within("", 6), Some(6));
+        assert_eq!(automaton.distance_within("", 5), None);
+        assert_eq!(automaton.distance_within("abcd", 3), None);
+        assert_eq!(automaton.distance_within("ac", 4), Some(4));
+        assert_eq!(automaton.distance_within("ac", 3), None);
+
+        let weights = Weights::new(1, 0, 1);
+        assert_eq!(weighted_levenshtein("kitten", "", weights), 0);
+        assert_eq!(weighted_levenshtein("kitten", "sitting", weights), 3);
+        assert_eq!(
+            weighted_levenshtein("a", "b", Weights::new(u32::MAX, 0, u32::MAX)),
+            u32::MAX
+        );
+    }
+
+    #[test]
+    fn substitutions() {
+        let weights = Weights::new(2, 2, 2);
+        let mut table = Substitutions::new(3);
+        table.insert('0', 'O', 1);
+        assert_eq!(table.cost('O', '0'), 1);
+        assert_eq!(table.cost('a', 'a'), 0);
+        assert_eq!(substitution_levenshtein("F00", "FOO", weights, &table), 2);
+        assert_eq!(substitution_levenshtein("F00", "FOX", weights, &table), 4);
+        // Deletion and insertion are cheaper than the default substitution
+        assert_eq!(substitution_levenshtein("ab", "ac", weights, &table), 3);
+        assert_eq!(
+            substitution_levenshtein("ab", "ac", Weights::new(1, 1, 3), &table),
+            2
+        );
+
+        let qwerty = Substitutions::qwerty(1, 2);
+        assert_eq!(qwerty.cost('q', 'w'), 1);
+        assert_eq!(qwerty.cost('a', 'q'), 1);
+        assert_eq!(qwerty.cost('a', 'w'), 1);
+        assert_eq!(qwerty.cost('a', 'e'), 2);
+        assert_eq!(qwerty.cost('G', 'H'), 1);
+        assert_eq!(qwerty.cost('p', '0'), 1);
+        assert_eq!(qwerty.cost('m', 'l'), 2);
+        let weights = Weights::new(2, 2, 2);
+        assert_eq!(
+            substitution_levenshtein("hello", "hwllo", weights, &qwerty),
+            1
+        );
+        assert_eq!(
+            substitution_levenshtein("hello", "hxllo", weights, &qwerty),
+            2
+        );
+
+        let ocr = Substitutions::ocr(1, 2);
+        assert_eq!(ocr.cost('0', 'O'), 1);
+        assert_eq!(ocr.cost('l', '1'), 1);
+        assert_eq!(substitution_levenshtein("B00K", "BOOK", weights, &ocr), 2);
+        assert_eq!(
+            substitution_levenshtein("modern", "rnodern", weights, &ocr),
+            1
+        );
+        assert_eq!(
+            substitution_levenshtein("rnodern", "modern", weights, &ocr),
+            1
+        );
+        assert_eq!(substitution_levenshtein("clear", "dear", weights, &ocr), 1);
+        let automaton = WeightedAutomaton::with_substitutions("modern", weights, &ocr);
+        assert_eq!(automaton.distance_within("rnodern", 1), Some(1));
+        assert_eq!(automaton.distance_within("rnoderrn", 1), None);
+    }
+
+    /// Distance from the full table, with sequences substituted as a whole
+    fn table_distance(a: &str, b: &str, weights: Weights, table: &Substitutions) -> u32 {
+        let a: Vec<char> = a.chars().collect();
+        let b: Vec<char> = b.chars().collect();
+        let mut d = vec![vec![0u32; b.len() + 1]; a.len() + 1];
+        for i in 0..=a.len() {
+            for j in 0..=b.len() {
+                let mut best = match (i, j) {
+                    (0, 0) => 0,
+                    _ => u32::MAX,
+                };
+                if i > 0 {
+                    best = best.min(d[i - 1][j] + weights.delete);
+                }
+                if j > 0 {
+                    best = best.min(d[i][j - 1] + weights.insert);
+                }
+                if i > 0 && j > 0 {
+                    best = best.min(d[i - 1][j - 1] + table.cost(a[i - 1], b[j - 1]));
+                }
+                for (x, y, cost) in &table.sequences {
+                    if a[..i].ends_with(x) && b[..j].ends_with(y) {
+                        best = best.min(d[i - x.len()][j - y.len()] + cost);
+                    }
+                }
+                d[i][j] = best;
+            }
+        }
+        d[a.len()][b.len()]
+    }
+
+    #[test]
+    fn sequences() {
+        let mut table = Substitutions::new(3);
+        table.insert_str("rn", "m", 1);
+        table.insert_str("abc", "x", 1);
+        table.insert_str("ab", "ba", 1);
+        table.insert_str("a", "q", 1);
+        table.insert_str("abc", "x", 2);
+        assert_eq!(table.cost('a', 'q'), 1);
+        assert_eq!(table.cost_str("x", "abc"), 2);
+        assert_eq!(table.cost_str("q", "a"), 1);
+        assert_eq!(table.cost_str("rn", "rn"), 0);
+        assert_eq!(table.cost_str("rn", "n"), 3);
+        assert_eq!(table.sequences.len(), 6);
+        let weights = Weights::new(2, 2, 3);
+        let strings = [
+            "", "m", "rn", "mm", "rnrn", "rnm", "arnb", "abc", "x", "xabc", "abcrn", "ba", "bab",
+            "abab", "qrnx",
+        ];
+        for a in strings {
+            for b in strings {
+                let expected = table_distance(a, b, weights, &table);
+                let automaton = WeightedAutomaton::with_substitutions(a, weights, &table);
+                assert_eq!(automaton.distance(b), expected, "{a} {b}");
+                assert_eq!(
+                    automaton.distance_within(b, 2),
+                    Some(expected).filter(|&x| x <= 2)
+                );
+            }
+        }
+
+        let trie = Trie::from_iter(["modern", "mm", "rnrn", "bar"].map(String::from));
+        let ocr = Substitutions::ocr(1, 2);
+        let automaton = WeightedAutomaton::with_substitutions("rnodern", weights, &ocr);
+        let found = trie.find_one_with(&automaton.start(), None);
+        assert_eq!(found, Some(("modern", 1)));
+        let automaton = WeightedAutomaton::with_substitutions("rnrn", weights, &ocr);
+        let mut all = trie.find_all_with(&automaton.start(), 2);
+        all.sort_unstable();
+        assert_eq!(all, vec![("mm", 2), ("rnrn", 0)]);
+    }
+
+    #[test]
+    fn automaton() {
+        let trie = Trie::from_iter(["foo", "bar", "baz", "ba", "foobar"].map(String::from));
+        for weights in [
+            Weights::new(1, 1, 1),
+            Weights::new(1, 3, 2),
+            Weights::new(3, 1, 1),
+        ] {
+            for query in ["", "ba", "bax", "foob", "qux", "fooba"] {
+                let automaton = WeightedAutomaton::new(query, weights);
+                let brute = trie
+                    .iter()
+                    .map(|x| (automaton.distance(x), x))
+                    .min()
+                    .unwrap();
+                let found = trie.find_one_with(&automaton.start(), None).unwrap();
+                assert_eq!(found.1, brute.0);
+                let all = trie.find_all_with(&automaton.start(), 2);
+                let count = trie.iter().filter(|x| automaton.distance(x) <= 2).count();
+                assert_eq!(all.len(), count);
+            }
+        }
+
+        let table = Substitutions::qwerty(1, 3);
+        let weights = Weights::new(2, 2, 3);
+        for query in ["", "ba", "bax", "voo", "qux", "fooba"] {
+            let automaton = WeightedAutomaton::with_substitutions(query, weights, &table);
+            let brute = trie
+                .iter()
+                .map(|x| (automaton.distance(x), x))
+                .min()
+                .unwrap();
+            let found = trie.find_one_with(&automaton.start(), None).unwrap();
+            assert_eq!(found.1, brute.0);
+            let all = trie.find_all_with(&automaton.start(), 3);
+            let count = trie.iter().filter(|x| automaton.distance(x) <= 3).count();
+            assert_eq!(all.len(), count);
+        }
+    }
+}
@@ -0,0 +1,239 @@
+# pylint: disable=missing-docstring
+# Tests of the paths only reachable from Python, run against a build of the
+# extension module, for example with `maturin develop` and then `pytest`.
+
+import pickle
+import sys
+from concurrent.futures import ThreadPoolExecutor
+
+import pytest
+
+import assrs
+
+WORDS = ["foo", "bar", "baz", "qux", "café", "naïve", "fooo", "ba"]
+QUERIES = ["fo", "bax", "cafe", "", "quux", "naive"]
+
+
+def test_batch():
+    trie = assrs.Trie(WORDS)
+    expected = [trie.find_one(x) for x in QUERIES]
+    assert trie.find_one_batch(QUERIES) == expected
+    assert trie.find_one_batch(QUERIES, max_edits=1) == [
+        trie.find_one(x, max_edits=1) for x in QUERIES
+    ]
+    assert trie.find_one_batch(QUERIES, metric="osa") == [
+        trie.find_one(x, metric="osa") for x in QUERIES
+    ]
+    assert trie.find_one_batch([]) == []
+
+    tree = assrs.BKTree(WORDS)
+    assert [x and x[1] for x in tree.find_one_batch(QUERIES)] == [
+        x and x[1] for x in expected
+    ]
+
+    extracted = assrs.levenshtein_extract_batch(QUERIES, WORDS)
+    assert extracted == [assrs.levenshtein_extract(x, WORDS) for x in QUERIES]
+    assert assrs.levenshtein_extract_batch(QUERIES, []) == [None] * len(QUERIES)
+
+
+def test_batch_errors():
+    trie = assrs.Trie(WORDS)
+    with pytest.raises(ValueError):
+        trie.find_one_batch(QUERIES, metric="hamming")
+
+    metric = Failing(100)
+    tree = assrs.BKTree(WORDS, metric=metric)
+    metric.budget = 3
+    with pytest.raises(RuntimeError, match="metric failed"):
+        tree.find_one_batch(QUERIES)
+
+
+def test_threads():
+    # Searches release the GIL, so threads share the index while searching
+    trie = assrs.Trie(WORDS * 10 + [f"{i:x}" for i in range(1000)])
+    tree = assrs.BKTree(WORDS)
+    expected = [(trie.find_all(x, 2), tree.find_k(x, 3)) for x in QUERIES]
+
+    def search(query):
+        return trie.find_all(query, 2), tree.find_k(query, 3)
+
+    with ThreadPoolExecutor(4) as pool:
+        assert list(pool.map(search, QUERIES * 10)) == expected * 10
+
+
+def test_cdist():
+    expected = [[assrs.levenshtein(a, b) for b in WORDS] for a in QUERIES]
+    result = assrs.cdist(QUERIES, WORDS)
+    assert [list(x) for x in result] == expected
+    cutoff = [[min(x, 2) for x in row] for row in expected]
+    assert [list(x) for x in assrs.cdist(QUERIES, WORDS, max_edits=1)] == cutoff
+
+
+def test_cdist_list(monkeypatch):
+    # Importing a module set to None raises ImportError
+    monkeypatch.setitem(sys.modules, "numpy", None)
+    expected = [[assrs.levenshtein(a, b) for b in WORDS] for a in QUERIES]
+    assert assrs.cdist(QUERIES, WORDS) == expected
+    assert assrs.cdist([], WORDS) == []
+    assert assrs.cdist(QUERIES, []) == [[] for _ in QUERIES]
+
+
+def test_cdist_numpy():
+    numpy = pytest.importorskip("numpy")
+    result = assrs.cdist(QUERIES, WORDS)
+    assert isinstance(result, numpy.ndarray)
+    assert result.dtype == numpy.uint32
+    assert result.shape == (len(QUERIES), len(WORDS))
+
+
+def test_pickle_trie():
+    trie = assrs.Trie(WORDS, segmentation="graphemes", normalizer="lowercase")
+    loaded = pickle.loads(pickle.dumps(trie))
+    assert loaded.values() == trie.values()
+    assert loaded.find_one("FOO") == ("foo", 0)
+    # One grapheme apart, but two code points
+    assert loaded.find_one("cafe\u0301") == ("caf\u00e9", 1)
+
+    trie_map = assrs.TrieMap({"foo": [1], "bar": {"x": 2}}, normalizer="casefold")
+    loaded = pickle.loads(pickle.dumps(trie_map))
+    assert loaded.items() == trie_map.items()
+    assert loaded.find_one("BAR") == ("bar", 0, {"x": 2})
+
+
+def test_pickle_bktree():
+    tree = assrs.BKTree(WORDS, metric="osa", segmentation="graphemes")
+    tree.remove("bar")
+    loaded = pickle.loads(pickle.dumps(tree))
+    assert loaded.values() == tree.values()
+    assert loaded.find_k("abz", 2) == tree.find_k("abz", 2)
+
+    tree_map = assrs.BKTreeMap({"foo": 1, "bar": 2}, metric="hamming")
+    loaded = pickle.loads(pickle.dumps(tree_map))
+    assert loaded.items() == tree_map.items()
+    assert loaded.find_one("fox") == ("foo", 1, 1)
+
+
+def length(a, b):
+    return abs(len(a) - len(b))
+
+
+def test_pickle_callable():
+    # Callables are pickled by reference, so must be importable
+    tree = assrs.BKTree(WORDS, metric=length)
+    loaded = pickle.loads(pickle.dumps(tree))
+    assert loaded.find_all("xyz", 0) == tree.find_all("xyz", 0)
+
+    tree_map = assrs.BKTreeMap({"foo": 1, "ba": 2}, metric=length)
+    loaded = pickle.loads(pickle.dumps(tree_map))
+    assert loaded.find_one("xy") == ("ba", 0, 2)
+
+
+def test_save_load(tmp_path):
+    path = tmp_path / "index.bin"
+    tree = assrs.BKTree(WORDS, metric=length)
+    tree.save(path)
+    with pytest.raises(ValueError):
+        assrs.BKTree.load(path)
+    assert assrs.BKTree.load(path, metric=length).values() == tree.values()
+
+    tree_map = assrs.BKTreeMap({"foo": 1}, metric="osa")
+    tree_map.save(path)
+    assert assrs.BKTreeMap.load(path).items() == [("foo", 1)]
+
+
+class Failing:
+    def __init__(self, budget):
+        self.budget = budget
+
+    def __call__(self, a, b):
+        self.budget -= 1
+        if self.budget < 0:
+            raise RuntimeError("metric failed")
+        return length(a, b)
+
+
+def test_callable_errors():
+    with pytest.raises(RuntimeError, match="metric failed"):
+        assrs.BKTree(WORDS, metric=Failing(2))
+
+    metric = Failing(100)
+    tree = assrs.BKTree(WORDS, metric=metric)
+    values = tree.values()
+    metric.budget = 0
+    with pytest.raises(RuntimeError):
+        tree.insert("quux")
+    with pytest.raises(RuntimeError):
+        tree.remove("foo")
+    with pytest.raises(RuntimeError):
+        tree.find_one("foo")
+    with pytest.raises(RuntimeError):
+        tree.find_all("foo", 1)
+    # The tree is unchanged and usable once the metric recovers
+    metric.budget = 100
+    assert tree.values() == values
+    assert tree.find_one("xyz", max_edits=0) == ("foo", 0)
+    tree.insert("quux")
+    assert tree.contains("quux")
+
+    tree = assrs.BKTreeMap({"foo": 1}, metric=lambda a, b: "far")
+    with pytest.raises(TypeError):
+        tree.insert("bar", 2)
+    assert tree.items() == [("foo", 1)]
+
+
+def test_frozen_from_trie():
+    trie = assrs.Trie(WORDS)
+    assert assrs.FrozenTrie.from_trie(trie).values() == sorted(WORDS)
+    for options in [{"normalizer": "lowercase"}, {"segmentation": "graphemes"}]:
+        with pytest.raises(ValueError, match="do not support"):
+            assrs.FrozenTrie.from_trie(assrs.Trie(WORDS, **options))
+
+
+def test_frozen_corrupt(tmp_path):
+    path = tmp_path / "frozen.bin"
+    assrs.FrozenTrie(WORDS).save(path)
+    data = path.read_bytes()
+    errors = 0
+    for i in range(len(data)):
+        flipped = bytearray(data)
+        flipped[i] ^= 0xFF
+        path.write_bytes(flipped)
+        # A corrupt file raises ValueError rather than panicking
+        try:
+            frozen = assrs.FrozenTrie.open(path)
+        except ValueError:
+            errors += 1
+            continue
+        frozen.values()
+        frozen.find_all("baz", 2)
+    assert errors > 0
+
+
+def test_min_similarity():
+    trie = assrs.Trie(WORDS)
+    tree = assrs.BKTree(WORDS)
+    for min_similarity in [-0.5, 1.5, float("nan")]:
+        with pytest.raises(ValueError, match="min_similarity"):
+            trie.find_one("foo", min_similarity=min_similarity)
+        with pytest.raises(ValueError, match="min_similarity"):
+            tree.find_one("foo", min_similarity=min_similarity)
+        with pytest.raises(ValueError, match="min_similarity"):
+            assrs.levenshtein_extract("foo", WORDS, min_similarity=min_similarity)
+    assert trie.find_one("fo", min_similarity=0.6) == ("foo", 1)
+    assert tree.find_one("fo", min_similarity=1.0) is None
+
+
+def test_too_many_graphemes():
+    # More distinct clusters of an ideograph and a combining mark than can be mapped
+    clusters = [
+        chr(c) + chr(m) for c in range(0x4E00, 0x5400) for m in range(0x300, 0x370)
+    ]
+    value = "".join(clusters[: 2 * 0xFFFE])
+    trie = assrs.Trie(["foo"], segmentation="graphemes")
+    with pytest.raises(ValueError, match="grapheme clusters"):
+        trie.insert(value)
+    assert trie.values() == ["foo"]
+    with pytest.raises(ValueError, match="grapheme clusters"):
+        assrs.TrieMap({value: 1}, segmentation="graphemes")
+    with pytest.raises(ValueError, match="grapheme clusters"):
+        assrs.levenshtein(value, "foo", segmentation="graphemes")